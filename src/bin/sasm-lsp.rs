@@ -0,0 +1,9 @@
+use std::io;
+use cpu_emulator::emulator::language_server::LanguageServer;
+
+// language server for .sasm files, speaking json-rpc over stdin and stdout
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    LanguageServer::new().run(&mut stdin.lock(), &mut stdout.lock()).unwrap();
+}
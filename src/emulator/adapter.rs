@@ -1,3 +1,7 @@
+use std::fmt;
+use crate::emulator::source_map::{SourceLocation, SourceMap};
+use crate::EmulatorErr;
+
 
 pub struct Port {
     input: u8,
@@ -12,6 +16,10 @@ impl Port {
         self.input
     }
 
+    pub fn set_input(&mut self, value: u8) {
+        self.input = value
+    }
+
     pub fn set_output(&mut self, immediate: u8) {
         self.output = immediate
     }
@@ -21,19 +29,87 @@ impl Port {
     }
 }
 
+// words reachable by a 4 bit jump immediate
+pub const BANK_SIZE: usize = 16;
+
 pub struct Rom {
     pub memory_array: Vec<u8>,
+    source_map: SourceMap,
 }
 impl Rom {
     pub fn new(memory_array: Vec<u8>) -> Self {
-        Self { memory_array }
+        Self { memory_array, source_map: SourceMap::new() }
+    }
+
+    pub fn with_source_map(memory_array: Vec<u8>, source_map: SourceMap) -> Self {
+        Self { memory_array, source_map }
+    }
+
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    pub fn source_location(&self, address: usize) -> Option<&SourceLocation> {
+        self.source_map.get(address)
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        self.memory_array[address]
+    }
+
+    pub fn size(&self) -> usize {
+        self.memory_array.len()
+    }
+
+    pub fn banks(&self) -> usize {
+        self.size().div_ceil(BANK_SIZE)
+    }
+
+    // words of one 16 word bank; the last bank may be shorter
+    pub fn bank(&self, bank: usize) -> &[u8] {
+        let start = (bank * BANK_SIZE).min(self.size());
+        let end = (start + BANK_SIZE).min(self.size());
+        &self.memory_array[start..end]
+    }
+}
+// data memory; each word holds one 4 bit register value
+pub struct Ram {
+    pub memory_array: Vec<u8>,
+}
+impl Ram {
+    pub fn new(size: usize) -> Self {
+        Self { memory_array: vec![0; size] }
     }
 
-    pub fn read(&self, pc: u8) -> u8 {
-        self.memory_array[pc as usize]
+    pub fn read(&self, address: usize) -> Result<u8, EmulatorErr> {
+        self.memory_array.get(address).copied()
+            .ok_or_else(|| self.out_of_range(address))
     }
 
-    pub fn size(&self) -> u8 {
-        self.memory_array.len() as u8
+    pub fn write(&mut self, address: usize, value: u8) -> Result<(), EmulatorErr> {
+        if address >= self.size() {
+            return Err(self.out_of_range(address));
+        }
+        self.memory_array[address] = value & 0x0f;
+        Ok(())
     }
-}
\ No newline at end of file
+
+    pub fn size(&self) -> usize {
+        self.memory_array.len()
+    }
+
+    fn out_of_range(&self, address: usize) -> EmulatorErr {
+        if self.memory_array.is_empty() {
+            EmulatorErr::new("No RAM on this machine")
+        } else {
+            EmulatorErr::new(&format!("RAM address {} out of range: size is {} words", address, self.size()))
+        }
+    }
+}
+
+impl fmt::Display for Ram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let words: Vec<String> = self.memory_array.iter().map(|w| format!("{:x}", w)).collect();
+        write!(f, "[{}]", words.join(" "))
+    }
+}
@@ -1,14 +1,68 @@
-use crate::lib::EmulatorErr;
+use crate::EmulatorErr;
 use crate::emulator::instructions::Token;
-use crate::emulator::register::RegisterOp;
+use crate::emulator::isa::Isa;
+use crate::emulator::source_map::{SourceLocation, SourceMap};
+use crate::emulator::td4::Td4;
 use std::default::Default;
 
 #[derive(Debug, Default)]
-pub struct Compiler;
+pub struct Compiler<I: Isa = Td4> {
+    isa: I,
+    fill: u8,
+    rom_size: Option<usize>,
+}
+
+// rom words being placed: the address the next word goes to and which words
+// are taken already
+struct Image {
+    words: Vec<u8>,
+    used: Vec<bool>,
+    cursor: usize,
+    fill: u8,
+}
+
+impl Image {
+    fn new(fill: u8) -> Self {
+        Image { words: Vec::new(), used: Vec::new(), cursor: 0, fill }
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), EmulatorErr> {
+        for word in words {
+            if self.cursor >= self.words.len() {
+                self.words.resize(self.cursor + 1, self.fill);
+                self.used.resize(self.cursor + 1, false);
+            }
+            if self.used[self.cursor] {
+                return Err(EmulatorErr::new(&format!("address {} is already used", self.cursor)));
+            }
+            self.words[self.cursor] = *word;
+            self.used[self.cursor] = true;
+            self.cursor += 1;
+        }
+        Ok(())
+    }
+}
 
 impl Compiler {
     pub fn new() -> Compiler {
-        Compiler::default()
+        Compiler { isa: Td4::new(), fill: 0, rom_size: None }
+    }
+}
+
+impl<I: Isa> Compiler<I> {
+    pub fn with_isa(isa: I) -> Self {
+        Self { isa, fill: 0, rom_size: None }
+    }
+
+    // value of the rom words no token places, 0 by default
+    pub fn set_fill(&mut self, fill: u8) {
+        self.fill = fill;
+    }
+
+    // the image is padded with the fill value up to rom_size words, so code
+    // that runs past the last placed word meets fill instead of the end of rom
+    pub fn set_rom_size(&mut self, rom_size: usize) {
+        self.rom_size = Some(rom_size);
     }
 
     pub fn compile(&self, tokens: Vec<Token>) -> Result<Vec<u8>, EmulatorErr> {
@@ -16,40 +70,59 @@ impl Compiler {
             return Err(EmulatorErr::new("instruction is empty"));
         };
 
-        let mut bin_codes = Vec::new();
+        let mut image = Image::new(self.fill);
 
         for token in tokens {
-            let code = match token {
-                Token::Add(RegisterOp::A, im) => { self.gen_binary(0b0000, im) },
-                Token::Add(RegisterOp::B, im) => { self.gen_binary(0b0101, im) },
-                Token::Mov(RegisterOp::A, im) => { self.gen_binary(0b0011, im) },
-                Token::Mov(RegisterOp::B, im) => { self.gen_binary(0b0111, im) },
-                Token::MovAB => { self.gen_binary_with_zero(0b0001) },
-                Token::MovBA => { self.gen_binary_with_zero(0b0100) },
-                Token::Jmp(im) => { self.gen_binary(0b1111, im) },
-                Token::Jnc(im) => { self.gen_binary(0b1110, im) },
-                Token::In(RegisterOp::A) => { self.gen_binary_with_zero(0b0010) },
-                Token::In(RegisterOp::B) => { self.gen_binary_with_zero(0b0110) },
-                Token::OutB => { self.gen_binary_with_zero(0b1001) },
-                Token::OutIm(im) => { self.gen_binary(0b1011, im) },
-            };
-            bin_codes.push(code);
+            self.emit(&mut image, token)?;
+        };
+
+        Ok(self.padded(image))
+    }
+
+    // compile and record the source position of every emitted instruction
+    pub fn compile_with_source_map(
+        &self,
+        tokens: Vec<Token>,
+        locations: &[SourceLocation],
+    ) -> Result<(Vec<u8>, SourceMap), EmulatorErr> {
+        if tokens.is_empty() {
+            return Err(EmulatorErr::new("instruction is empty"));
         };
+        if tokens.len() != locations.len() {
+            return Err(EmulatorErr::new("token and source location count mismatch"));
+        }
+
+        let mut image = Image::new(self.fill);
+        let mut source_map = SourceMap::new();
 
-        Ok(bin_codes)
+        for (token, location) in tokens.into_iter().zip(locations) {
+            if token.size() > 0 && !matches!(token, Token::Reserve(_)) {
+                source_map.insert(image.cursor, location.clone());
+            }
+            self.emit(&mut image, token)
+                .map_err(|e| EmulatorErr::at(location, e.msg()))?;
+        }
+
+        Ok((self.padded(image), source_map))
     }
 
-    fn gen_binary(&self, op: u8, im: u8) -> u8 {
-        let shift_op = op << 4;
-        let shift_data = im & 0x0f;
-        shift_op | shift_data
+    fn padded(&self, image: Image) -> Vec<u8> {
+        let mut words = image.words;
+        if let Some(rom_size) = self.rom_size {
+            words.resize(words.len().max(rom_size), self.fill);
+        }
+        words
     }
 
-    #[allow(clippy::erasing_op)]
-    fn gen_binary_with_zero(&self, op: u8) -> u8 {
-        let shift_op = op << 4;
-        let shift_data = 0b0000 & 0x0f;
-        shift_op | shift_data
+    // org moves the cursor anywhere, reserve skips words; writing a word twice is an error
+    fn emit(&self, image: &mut Image, token: Token) -> Result<(), EmulatorErr> {
+        match token {
+            Token::Org(address) => image.cursor = address,
+            Token::Reserve(count) => image.cursor += count,
+            Token::Byte(value) => image.write(&[value])?,
+            token => image.write(&self.isa.encode(&token)?)?,
+        }
+        Ok(())
     }
 }
 
@@ -57,8 +130,37 @@ impl Compiler {
 #[cfg(test)]
 mod compiler_tests {
     use crate::emulator::compiler::Compiler;
-    use crate::emulator::instructions::Token;
+    use crate::emulator::instructions::{Address, AluOp, Token};
     use crate::emulator::register::RegisterOp;
+    use crate::emulator::source_map::SourceLocation;
+
+    #[test]
+    fn compile_with_source_map_test() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::Mov(RegisterOp::A, 1), Token::OutB);
+        let locations = vec!(
+            SourceLocation::new("test.sasm", 1, 1),
+            SourceLocation::new("test.sasm", 2, 1),
+        );
+        let (code, source_map) = compiler.compile_with_source_map(token, &locations).unwrap();
+        assert_eq!(code, vec!(0b00110001, 0b10010000));
+        assert_eq!(source_map.get(1), Some(&locations[1]));
+        assert_eq!(source_map.get(2), None);
+    }
+
+    #[test]
+    fn compile_with_source_map_operand_test() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::Ld(Address::Direct(0b0011)), Token::OutB);
+        let locations = vec!(
+            SourceLocation::new("test.sasm", 1, 1),
+            SourceLocation::new("test.sasm", 2, 1),
+        );
+        let (code, source_map) = compiler.compile_with_source_map(token, &locations).unwrap();
+        assert_eq!(code, vec!(0b10000000, 0b00000011, 0b10010000));
+        assert_eq!(source_map.get(1), None);
+        assert_eq!(source_map.get(2), Some(&locations[1]));
+    }
 
     #[test]
     fn compile_test_add_a() {
@@ -76,6 +178,54 @@ mod compiler_tests {
         assert_eq!(code, vec!(0b01010001));
     }
 
+    #[test]
+    fn compile_test_sub() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::Sub(RegisterOp::A, 1), Token::Sub(RegisterOp::B, 1));
+        let code = compiler.compile(token).unwrap();
+        assert_eq!(code, vec!(0b10100001, 0b10000101, 0b00000001));
+    }
+
+    #[test]
+    fn compile_test_cmp() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::Cmp(RegisterOp::A, 1), Token::Cmp(RegisterOp::B, 1));
+        let code = compiler.compile(token).unwrap();
+        assert_eq!(code, vec!(0b10000110, 0b00000001, 0b10000111, 0b00000001));
+    }
+
+    #[test]
+    fn compile_test_alu() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::Alu(AluOp::Xor, RegisterOp::A), Token::Alu(AluOp::Rcr, RegisterOp::B));
+        let code = compiler.compile(token).unwrap();
+        assert_eq!(code, vec!(0b10001000, 0b00000011, 0b10001000, 0b00011000));
+    }
+
+    #[test]
+    fn compile_test_call_ret() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::Call(0b00010010), Token::Ret);
+        let code = compiler.compile(token).unwrap();
+        assert_eq!(code, vec!(0b10001001, 0b00010010, 0b10001010));
+    }
+
+    #[test]
+    fn compile_test_hlt_nop() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::Nop, Token::Hlt);
+        let code = compiler.compile(token).unwrap();
+        assert_eq!(code, vec!(0b10001100, 0b10001011));
+    }
+
+    #[test]
+    fn compile_test_interrupt() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::Ei, Token::Di, Token::Reti);
+        let code = compiler.compile(token).unwrap();
+        assert_eq!(code, vec!(0b10001110, 0b10001111, 0b10001101));
+    }
+
     #[test]
     fn compile_test_mov_a() {
         let compiler = Compiler::new();
@@ -124,6 +274,22 @@ mod compiler_tests {
         assert_eq!(code, vec!(0b11100001));
     }
 
+    #[test]
+    fn compile_test_jz() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::Jz(1));
+        let code = compiler.compile(token).unwrap();
+        assert_eq!(code, vec!(0b11000001));
+    }
+
+    #[test]
+    fn compile_test_jnz() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::Jnz(1));
+        let code = compiler.compile(token).unwrap();
+        assert_eq!(code, vec!(0b11010001));
+    }
+
     #[test]
     fn compile_test_in_a() {
         let compiler = Compiler::new();
@@ -155,4 +321,54 @@ mod compiler_tests {
         let code = compiler.compile(token).unwrap();
         assert_eq!(code, vec!(0b10110001));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn compile_test_ld() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::Ld(Address::Direct(0b00010010)), Token::Ld(Address::IndexB));
+        let code = compiler.compile(token).unwrap();
+        assert_eq!(code, vec!(0b10000000, 0b00010010, 0b10000010));
+    }
+
+    #[test]
+    fn compile_test_st() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::St(Address::Direct(0b00010010)), Token::St(Address::IndexB));
+        let code = compiler.compile(token).unwrap();
+        assert_eq!(code, vec!(0b10000001, 0b00010010, 0b10000011));
+    }
+
+    #[test]
+    fn compile_test_bank() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::Bank(0b0010));
+        let code = compiler.compile(token).unwrap();
+        assert_eq!(code, vec!(0b10000100, 0b00000010));
+    }
+
+    #[test]
+    fn compile_test_org() {
+        let compiler = Compiler::new();
+        let token = vec!(Token::OutB, Token::Org(3), Token::OutB);
+        let code = compiler.compile(token).unwrap();
+        assert_eq!(code, vec!(0b10010000, 0, 0, 0b10010000));
+
+        let token = vec!(Token::OutB, Token::OutB, Token::Org(1), Token::OutB);
+        assert_eq!(compiler.compile(token).unwrap_err().msg(), "address 1 is already used");
+
+        let token = vec!(Token::Org(2), Token::OutB, Token::Org(0), Token::Byte(0b10101010));
+        assert_eq!(compiler.compile(token).unwrap(), vec!(0b10101010, 0, 0b10010000));
+    }
+
+    #[test]
+    fn compile_test_fill() {
+        let mut compiler = Compiler::new();
+        compiler.set_fill(0b11111111);
+        let token = vec!(Token::Org(1), Token::Byte(0), Token::Org(3), Token::OutB);
+        assert_eq!(compiler.compile(token).unwrap(), vec!(0b11111111, 0, 0b11111111, 0b10010000));
+
+        compiler.set_rom_size(6);
+        let token = vec!(Token::Byte(0), Token::OutB);
+        assert_eq!(compiler.compile(token).unwrap(), vec!(0, 0b10010000, 0b11111111, 0b11111111, 0b11111111, 0b11111111));
+    }
+}
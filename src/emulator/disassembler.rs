@@ -0,0 +1,163 @@
+use crate::emulator::isa::Isa;
+use crate::emulator::td4::Td4;
+use crate::EmulatorErr;
+use std::collections::HashSet;
+use std::default::Default;
+
+#[derive(Debug, Default)]
+pub struct Disassembler<I: Isa = Td4> {
+    isa: I,
+}
+
+impl Disassembler {
+    pub fn new() -> Disassembler {
+        Disassembler { isa: Td4::new() }
+    }
+}
+
+impl<I: Isa> Disassembler<I> {
+    pub fn with_isa(isa: I) -> Self {
+        Self { isa }
+    }
+
+    // one .sasm line per instruction, in the syntax Parser accepts
+    pub fn disassemble(&self, bin_codes: &[u8]) -> Result<Vec<String>, EmulatorErr> {
+        Ok(self.listing(bin_codes)?
+            .into_iter()
+            .map(|(_, text)| text)
+            .collect())
+    }
+
+    // rom address and text of every instruction
+    pub fn listing(&self, bin_codes: &[u8]) -> Result<Vec<(usize, String)>, EmulatorErr> {
+        let mut lines = Vec::new();
+        let mut address = 0;
+
+        while address < bin_codes.len() {
+            let (text, size) = self.instruction(bin_codes, address)
+                .map_err(|e| EmulatorErr::new(&format!("address {}: {}", address, e)))?;
+            lines.push((address, text));
+            address += size;
+        }
+
+        Ok(lines)
+    }
+
+    // listing of a rom whose instructions start at starts; the words between
+    // them are data, a run of one value is written as .fill
+    pub fn listing_with_data(&self, bin_codes: &[u8], starts: &[usize]) -> Result<Vec<(usize, String)>, EmulatorErr> {
+        let starts: HashSet<usize> = starts.iter().copied().collect();
+        let mut lines = Vec::new();
+        let mut address = 0;
+
+        while address < bin_codes.len() {
+            if starts.contains(&address) {
+                let (text, size) = self.instruction(bin_codes, address)
+                    .map_err(|e| EmulatorErr::new(&format!("address {}: {}", address, e)))?;
+                lines.push((address, text));
+                address += size;
+                continue;
+            }
+
+            let value = bin_codes[address];
+            let count = bin_codes[address..].iter().enumerate()
+                .take_while(|(offset, word)| **word == value && !starts.contains(&(address + offset)))
+                .count();
+            let text = match count {
+                1 => format!(".byte {:08b}", value),
+                _ => format!(".fill {:b}, {:08b}", count, value),
+            };
+            lines.push((address, text));
+            address += count;
+        }
+
+        Ok(lines)
+    }
+
+    // text of the instruction at address and the number of words it uses
+    pub fn instruction(&self, bin_codes: &[u8], address: usize) -> Result<(String, usize), EmulatorErr> {
+        let (instruction, size) = self.isa.decode(bin_codes, address)?;
+        Ok((self.isa.disassemble(&instruction)?, size))
+    }
+}
+
+
+#[cfg(test)]
+mod disassembler_tests {
+    use crate::emulator::compiler::Compiler;
+    use crate::emulator::disassembler::Disassembler;
+    use crate::emulator::parser::Parser;
+
+    #[test]
+    fn disassemble_round_trip_test() {
+        let source = vec![
+            "mov A 0001",
+            "add B 1111",
+            "mov A B",
+            "mov B A",
+            "jz 0011",
+            "jnz 0000",
+            "jnc 0001",
+            "jmp 0010",
+            "in A",
+            "out B",
+            "out 1010",
+            "ld A [00010000]",
+            "st A [B]",
+            "bank 0001",
+            "sub A 0001",
+            "sub B 0010",
+            "cmp A 0011",
+            "cmp B 0100",
+            "add A B",
+            "and B A",
+            "or A B",
+            "xor A B",
+            "not B",
+            "shl A",
+            "shr B",
+            "rcl A",
+            "rcr B",
+            "call 00010000",
+            "ret",
+            "nop",
+            "hlt",
+            "ei",
+            "di",
+            "reti",
+        ];
+
+        let mut parser = Parser::new(source.iter().map(|s| s.to_string()).collect());
+        let code = Compiler::new().compile(parser.parse().unwrap()).unwrap();
+        let text = Disassembler::new().disassemble(&code).unwrap();
+
+        assert_eq!(text, source);
+    }
+
+    #[test]
+    fn listing_test() {
+        let disassembler = Disassembler::new();
+        let listing = disassembler.listing(&[0b10000000, 0b00000011, 0b11000000]).unwrap();
+        assert_eq!(listing, vec![(0, "ld A [00000011]".to_string()), (2, "jz 0000".to_string())]);
+
+        assert!(disassembler.listing(&[0b10001000, 0b00001111]).is_err());
+        assert!(disassembler.listing(&[0b10000000]).is_err());
+    }
+
+    #[test]
+    fn listing_with_data_test() {
+        let disassembler = Disassembler::new();
+        // out B, .byte 10001000, 00001111, then fill words up to the rom size
+        let rom = [0b10010000, 0b10001000, 0b00001111, 0, 0, 0];
+        let listing = disassembler.listing_with_data(&rom, &[0]).unwrap();
+        assert_eq!(listing, vec![
+            (0, "out B".to_string()),
+            (1, ".byte 10001000".to_string()),
+            (2, ".byte 00001111".to_string()),
+            (3, ".fill 11, 00000000".to_string()),
+        ]);
+
+        let listing = disassembler.listing_with_data(&[0, 0, 0], &[1]).unwrap();
+        assert_eq!(listing, vec![(0, ".byte 00000000".to_string()), (1, "add A 0000".to_string()), (2, ".byte 00000000".to_string())]);
+    }
+}
@@ -1,17 +1,45 @@
-use num_traits::FromPrimitive;
+use std::fmt;
 use crate::{
     emulator::{
-        instructions::Opcodes,
-        register::Register,
-        adapter::{ Port, Rom }
+        instructions::AluOp,
+        isa::Isa,
+        td4::Td4,
+        register::{ Register, RegisterOp },
+        adapter::{ Port, Ram, Rom, BANK_SIZE },
+        machine::{ MachineConfig, INTERRUPT_VECTOR },
+        memory_map::{ MemoryMap, Target },
+        snapshot::Snapshot,
     },
-    lib::EmulatorErr
+    EmulatorErr
 };
 
-pub struct CPUemulator {
+// why execute stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    Hlt,
+    EndOfRom,
+}
+
+impl fmt::Display for HaltReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HaltReason::Hlt => write!(f, "hlt instruction"),
+            HaltReason::EndOfRom => write!(f, "end of rom"),
+        }
+    }
+}
+
+pub struct CPUemulator<I: Isa = Td4> {
+    pub isa: I,
     pub register: Register,
     pub port: Port,
     pub rom: Rom,
+    pub ram: Ram,
+    pub memory_map: MemoryMap,
+    pub config: MachineConfig,
+    // set once the machine stops; execute does nothing until it is cleared
+    pub halted: Option<HaltReason>,
+    interrupt_request: bool,
 }
 
 impl CPUemulator {
@@ -19,164 +47,451 @@ impl CPUemulator {
         register: Register,
         port: Port,
         rom: Rom,
-    ) -> Self {
-        if rom.size() > 16 {
-            panic!("Overflow rom size: maximum is 16 bytes")
+    ) -> Result<Self, EmulatorErr> {
+        Self::with_config(MachineConfig::td4(), register, port, rom)
+    }
+
+    pub fn with_config(
+        config: MachineConfig,
+        register: Register,
+        port: Port,
+        rom: Rom,
+    ) -> Result<Self, EmulatorErr> {
+        Self::with_isa(Td4::new(), config, register, port, rom)
+    }
+}
+
+impl<I: Isa> CPUemulator<I> {
+    pub fn with_isa(
+        isa: I,
+        config: MachineConfig,
+        register: Register,
+        port: Port,
+        rom: Rom,
+    ) -> Result<Self, EmulatorErr> {
+        config.validate()?;
+        if rom.size() > config.rom_size() {
+            return Err(EmulatorErr::new(&format!(
+                "Overflow rom size: {} words given, maximum is {} words",
+                rom.size(), config.rom_size()
+            )));
         }
-        Self { register, port, rom}
+        if register.pc() & !config.pc_mask() != 0 {
+            return Err(EmulatorErr::new(&format!(
+                "Program counter {} does not fit in {} bits",
+                register.pc(), config.address_width()
+            )));
+        }
+        let ram = Ram::new(config.ram_size());
+        let memory_map = MemoryMap::with_ram(config.ram_size());
+        Ok(Self { isa, register, port, rom, ram, memory_map, config, halted: None, interrupt_request: false })
     }
 
-   pub fn fetch(&self) -> u8 {
-        let pc = self.register.pc();
-        if self.rom.size() <= pc {
-            return 0
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.register.pc(),
+            bank: self.register.bank(),
+            register_a: self.register.register_a(),
+            register_b: self.register.register_b(),
+            carry_flag: self.register.carry_flag(),
+            zero_flag: self.register.zero_flag(),
+            interrupt_enable: self.register.interrupt_enable(),
+            interrupt_pending: self.interrupt_request,
+            input: self.input(),
+            output: self.port.output(),
+            ram: self.ram.memory_array.clone(),
+            stack: self.register.stack().to_vec(),
+            halted: self.halted,
         }
+    }
 
-        self.rom.read(pc)
-    }
-
-    pub fn decode(&self, data: u8) -> Result<(Opcodes, u8), EmulatorErr> {
-        let op = data >> 4;
-        let im = data & 0x0f;
-
-        if let Some(opcode) = FromPrimitive::from_u8(op) {
-            match opcode {
-                Opcodes::AddA
-                | Opcodes::AddB
-                | Opcodes::MovA
-                | Opcodes::MovB
-                | Opcodes::MovA2B
-                | Opcodes::MovB2A
-                | Opcodes::Jmp
-                | Opcodes::Jnc
-                | Opcodes::OutIm => Ok((opcode, im)),
-                Opcodes::InA
-                | Opcodes::InB
-                | Opcodes::OutB => Ok((opcode, 0)),
-            }
-        } else {
-            Err(EmulatorErr::new("No match opcode"))
+    // raise the interrupt line; it stays raised until the handler is entered
+    pub fn raise_interrupt(&mut self) {
+        self.interrupt_request = true
+    }
+
+    pub fn interrupt_pending(&self) -> bool {
+        self.interrupt_request
+    }
+
+    // drive the input switches, a change raises the interrupt line
+    pub fn set_input(&mut self, value: u8) {
+        if self.port.input() != value {
+            self.port.set_input(value);
+            self.raise_interrupt();
         }
     }
 
-    pub fn execute(&mut self) -> Result<(), EmulatorErr> {
+    pub fn execute(&mut self) -> Result<HaltReason, EmulatorErr> {
         loop {
-            let data = self.fetch();
-            let (opcode, immediate) = self.decode(data).unwrap();
+            // interrupts are only taken between instructions, before the next fetch
+            if self.memory_map.poll_interrupts() {
+                self.raise_interrupt();
+            }
+            let accept = self.interrupt_request && self.register.interrupt_enable() == 1;
+            if accept && self.halted == Some(HaltReason::Hlt) {
+                self.halted = None;
+            }
+            if accept && self.halted.is_none() {
+                self.enter_interrupt()?;
+            }
+
+            if self.halted.is_none() && self.end_of_rom() {
+                self.halted = Some(HaltReason::EndOfRom);
+            }
+            if let Some(reason) = self.halted {
+                return Ok(reason);
+            }
+
+            let pc = self.register.pc();
+            let (instruction, size) = self.isa.decode(&self.rom.memory_array, pc as usize)
+                .map_err(|e| self.error_at(pc, &e))?;
 
             println!("--------------------------------------------");
-            println!("fetch: {}", format!("{:#b})", data));
-            println!("opcode: {:?}", format!("{:?}", opcode));
-            println!("immediate: {}", format!("{:#b})", immediate));
-
-            match opcode {
-                Opcodes::AddA => self.add_a(immediate),
-                Opcodes::AddB => self.add_b(immediate),
-                Opcodes::MovA => self.mov_a(immediate),
-                Opcodes::MovB => self.mov_b(immediate),
-                Opcodes::MovA2B => self.mov_a2b(),
-                Opcodes::MovB2A => self.mov_b2a(),
-                Opcodes::Jmp => self.jmp(immediate),
-                Opcodes::Jnc => self.jnc(immediate),
-                Opcodes::InA => self.in_a(),
-                Opcodes::InB => self.in_b(),
-                Opcodes::OutB => self.out_b(),
-                Opcodes::OutIm => self.out_im(immediate),
-            };
-
-            if opcode != Opcodes::Jmp || opcode != Opcodes::Jnc {
-                self.register.inc_pc();
+            if let Some(location) = self.rom.source_location(pc as usize) {
+                println!("source: {}", location);
             }
+            println!("fetch: {:#b}", self.rom.read(pc as usize));
+            println!("instruction: {:?}", instruction);
+
+            // instructions that load the pc overwrite this
+            self.register.set_pc(pc.wrapping_add(size as u16));
+            let isa = self.isa.clone();
+            isa.execute(self, instruction)
+                .map_err(|e| self.error_at(pc, &e))?;
+            self.memory_map.tick_devices();
 
+            let registers: Vec<String> = self.isa.registers().iter()
+                .map(|r| format!("{}: {:0width$b}", r.name, (r.read)(&self.register), width = r.width as usize))
+                .collect();
+            println!("{}", registers.join(" "));
             println!("{}", self.register.pc());
-            println!("{}", self.halt());
+            println!("{}", self.end_of_rom());
             println!("{}", self.rom.size());
+            if self.ram.size() > 0 {
+                println!("ram: {}", self.ram);
+            }
+            if !self.register.stack().is_empty() {
+                println!("stack: {:?}", self.register.stack());
+            }
 
-            if self.halt() {
-                return Ok(());
+            // the program counter wraps at the configured address width, a pc past
+            // the end of rom is left alone so the next round stops on it
+            if !self.end_of_rom() {
+                let pc = self.register.pc() & self.config.pc_mask();
+                self.register.set_pc(pc);
             }
         }
     }
 
-    fn halt(&self)-> bool {
-        self.rom.size() <= self.register.pc()
+    // prefix an error with the source position of the instruction at pc
+    fn error_at(&self, pc: u16, err: &EmulatorErr) -> EmulatorErr {
+        match self.rom.source_location(pc as usize) {
+            Some(location) => EmulatorErr::at(location, err.msg()),
+            None => EmulatorErr::new(&format!("address {}: {}", pc, err)),
+        }
     }
 
-    fn add_a(&mut self, immediate: u8) {
-        let register_a = self.register.register_a();
-        let value = register_a + immediate;
-        if value > 0x0f {
-            self.register.set_carry_flag(1);
+    // save pc and carry, mask further interrupts and go to the vector; there is
+    // one save slot, so a handler that enables interrupts again must not be interrupted
+    fn enter_interrupt(&mut self) -> Result<(), EmulatorErr> {
+        if self.register.in_interrupt() {
+            let pc = self.register.pc();
+            return Err(self.error_at(pc, &EmulatorErr::new("Nested interrupt: the handler has not returned with reti")));
         }
-        self.register.set_register_a(value & 0x0f);
+        println!("--------------------------------------------");
+        println!("interrupt: return to {}", self.register.pc());
+        self.interrupt_request = false;
+        self.register.save_interrupt_frame(self.register.pc(), self.register.carry_flag());
+        self.register.set_interrupt_enable(0);
+        self.load_pc(INTERRUPT_VECTOR);
+        Ok(())
+    }
+
+    pub(crate) fn reti(&mut self) -> Result<(), EmulatorErr> {
+        let (pc, carry) = self.register.take_interrupt_frame()
+            .ok_or_else(|| EmulatorErr::new("reti outside an interrupt handler"))?;
+        self.load_pc(pc);
+        self.register.set_carry_flag(carry);
+        self.register.set_interrupt_enable(1);
+        Ok(())
+    }
+
+    pub(crate) fn set_interrupt_enable(&mut self, value: u8) {
+        self.register.set_interrupt_enable(value);
+        self.register.set_carry_flag(0);
+    }
+
+    fn end_of_rom(&self) -> bool {
+        self.rom.size() <= self.register.pc() as usize
     }
 
-    fn add_b(&mut self, immediate: u8) {
+    pub(crate) fn call(&mut self, address: u8) -> Result<(), EmulatorErr> {
+        let depth = self.config.stack_depth();
+        if depth == 0 {
+            return Err(EmulatorErr::new("No return stack on this machine"));
+        }
+        // the target is one 8 bit word, it cannot reach the whole of a wider rom
+        if self.config.address_width() > 8 {
+            return Err(EmulatorErr::new(&format!(
+                "call takes an 8 bit address, this machine has {} bit addresses", self.config.address_width()
+            )));
+        }
+        if self.register.stack().len() >= depth {
+            if !self.config.stack_wrap() {
+                return Err(EmulatorErr::new(&format!("Stack overflow: depth is {}", depth)));
+            }
+            self.register.drop_stack_bottom();
+        }
+
+        // pc is already on the instruction after the call
+        let return_address = self.register.pc();
+        self.register.push_stack(return_address);
+        self.load_pc(address as u16);
+        self.register.set_carry_flag(0);
+        Ok(())
+    }
+
+    pub(crate) fn ret(&mut self) -> Result<(), EmulatorErr> {
+        let return_address = match self.register.pop_stack() {
+            Some(address) => address,
+            None if self.config.stack_wrap() => 0,
+            None => return Err(EmulatorErr::new("Stack underflow: ret without call")),
+        };
+        self.load_pc(return_address);
+        self.register.set_carry_flag(0);
+        Ok(())
+    }
+
+    // absolute program counter load; a banked machine follows with its bank register
+    fn load_pc(&mut self, address: u16) {
+        if self.config.banked() {
+            self.register.set_bank((address as usize / BANK_SIZE) as u8);
+        }
+        self.register.set_pc(address);
+    }
+
+    pub(crate) fn select_bank(&mut self, bank: u8) -> Result<(), EmulatorErr> {
+        if !self.config.banked() {
+            return Err(EmulatorErr::new("Rom bank switching is not enabled"));
+        }
+        if bank as usize >= self.config.rom_banks() {
+            return Err(EmulatorErr::new(&format!(
+                "Rom bank {} out of range: machine has {} banks",
+                bank, self.config.rom_banks()
+            )));
+        }
+        self.register.set_bank(bank);
+        Ok(())
+    }
+
+    // jump immediates address the 16 words of the selected bank
+    fn jump_target(&self, immediate: u8) -> u16 {
+        if self.config.banked() {
+            (self.register.bank() as u16) * BANK_SIZE as u16 + immediate as u16
+        } else {
+            immediate as u16
+        }
+    }
+
+    // writes to the reserved bank select values never reach the port
+    fn write_output(&mut self, value: u8) -> Result<bool, EmulatorErr> {
+        match self.config.bank_port_base() {
+            Some(base) if value >= base => {
+                self.select_bank(value - base)?;
+                Ok(false)
+            },
+            _ => {
+                self.port.set_output(value);
+                Ok(true)
+            },
+        }
+    }
+
+    // ld/st go through the memory map to ram, rom, the port or a device
+    fn load(&mut self, address: usize) -> Result<u8, EmulatorErr> {
+        match self.memory_map.resolve(address)? {
+            (Target::Ram { base }, offset) => self.ram.read(*base + offset),
+            (Target::Rom { base }, offset) => {
+                let rom_address = *base + offset;
+                if rom_address >= self.rom.size() {
+                    return Err(EmulatorErr::new(&format!("Rom address {} out of range", rom_address)));
+                }
+                Ok(self.rom.read(rom_address) & 0x0f)
+            },
+            (Target::Port, _) => Ok(self.input()),
+            (Target::Device(device), offset) => Ok(device.read(offset)? & 0x0f),
+        }
+    }
+
+    fn store(&mut self, address: usize, value: u8) -> Result<(), EmulatorErr> {
+        match self.memory_map.resolve(address)? {
+            (Target::Ram { base }, offset) => self.ram.write(*base + offset, value),
+            (Target::Rom { .. }, _) => Err(EmulatorErr::new(&format!("Memory address {} is read only rom", address))),
+            (Target::Port, _) => {
+                if self.write_output(value)? {
+                    println!("port (st) output: {}", self.port.output());
+                }
+                Ok(())
+            },
+            (Target::Device(device), offset) => device.write(offset, value),
+        }
+    }
+
+    pub(crate) fn ld_a(&mut self, address: usize) -> Result<(), EmulatorErr> {
+        let value = self.load(address)?;
+        self.register.set_register_a(value);
+        self.register.set_carry_flag(0);
+        Ok(())
+    }
+
+    pub(crate) fn st_a(&mut self, address: usize) -> Result<(), EmulatorErr> {
+        let register_a = self.register.register_a();
+        self.store(address, register_a)?;
+        self.register.set_carry_flag(0);
+        Ok(())
+    }
+
+    pub(crate) fn add_a(&mut self, immediate: u8) {
+        let register_a = self.register.register_a();
+        let value = self.add_flags(register_a + immediate);
+        self.register.set_register_a(value);
+    }
+
+    pub(crate) fn add_b(&mut self, immediate: u8) {
         let register_b = self.register.register_b();
-        let value = register_b + immediate;
-        if value > 0x0f {
-            self.register.set_carry_flag(1);
+        let value = self.add_flags(register_b + immediate);
+        self.register.set_register_b(value);
+    }
+
+    // carry on a 4 bit overflow, zero when the 4 bit result is 0
+    fn add_flags(&mut self, value: u8) -> u8 {
+        let result = value & 0x0f;
+        self.register.set_carry_flag((value > 0x0f) as u8);
+        self.register.set_zero_flag((result == 0) as u8);
+        result
+    }
+
+    pub(crate) fn sub_a(&mut self, immediate: u8) {
+        let register_a = self.register.register_a();
+        let value = self.sub_flags(register_a, immediate);
+        self.register.set_register_a(value);
+    }
+
+    pub(crate) fn sub_b(&mut self, immediate: u8) {
+        let register_b = self.register.register_b();
+        let value = self.sub_flags(register_b, immediate);
+        self.register.set_register_b(value);
+    }
+
+    // carry holds the borrow, zero is set when both sides are equal
+    pub(crate) fn sub_flags(&mut self, lhs: u8, rhs: u8) -> u8 {
+        let result = lhs.wrapping_sub(rhs) & 0x0f;
+        self.register.set_carry_flag((lhs < rhs) as u8);
+        self.register.set_zero_flag((result == 0) as u8);
+        result
+    }
+
+    pub(crate) fn alu(&mut self, op: AluOp, register: RegisterOp) -> Result<(), EmulatorErr> {
+        if !self.config.extended_alu() {
+            return Err(EmulatorErr::new("Extended alu instructions are not enabled on this machine"));
+        }
+
+        let (destination, source) = match register {
+            RegisterOp::A => (self.register.register_a(), self.register.register_b()),
+            RegisterOp::B => (self.register.register_b(), self.register.register_a()),
+        };
+        let carry = self.register.carry_flag();
+
+        // (result including a carry out in bit 4, new carry)
+        let (value, carry) = match op {
+            AluOp::Add => (destination + source, ((destination + source) > 0x0f) as u8),
+            AluOp::And => (destination & source, 0),
+            AluOp::Or => (destination | source, 0),
+            AluOp::Xor => (destination ^ source, 0),
+            AluOp::Not => (!destination, 0),
+            AluOp::Shl => (destination << 1, destination >> 3),
+            AluOp::Shr => (destination >> 1, destination & 0b0001),
+            AluOp::Rcl => ((destination << 1) | carry, destination >> 3),
+            AluOp::Rcr => ((destination >> 1) | (carry << 3), destination & 0b0001),
+        };
+
+        let result = value & 0x0f;
+        self.register.set_carry_flag(carry);
+        self.register.set_zero_flag((result == 0) as u8);
+        match register {
+            RegisterOp::A => self.register.set_register_a(result),
+            RegisterOp::B => self.register.set_register_b(result),
         }
-        self.register.set_register_b(value & 0x0f);
+        Ok(())
     }
 
-    fn mov_a(&mut self, immediate: u8) {
+    pub(crate) fn mov_a(&mut self, immediate: u8) {
         self.register.set_register_a(immediate);
         self.register.set_carry_flag(0)
     }
 
-    fn mov_b(&mut self, immediate: u8) {
+    pub(crate) fn mov_b(&mut self, immediate: u8) {
         self.register.set_register_b(immediate);
         self.register.set_carry_flag(0)
     }
 
-    fn mov_a2b(&mut self) {
+    pub(crate) fn mov_a2b(&mut self) {
         let register_b = self.register.register_b();
         self.register.set_register_a(register_b);
         self.register.set_carry_flag(0)
     }
 
-    fn mov_b2a(&mut self) {
+    pub(crate) fn mov_b2a(&mut self) {
         let register_a = self.register.register_a();
         self.register.set_register_b(register_a);
         self.register.set_carry_flag(0)
     }
 
-    fn jmp(&mut self, immediate: u8) {
-        self.register.set_pc(immediate);
+    pub(crate) fn jmp(&mut self, immediate: u8) {
+        self.register.set_pc(self.jump_target(immediate));
         self.register.set_carry_flag(0);
     }
 
-    fn jnc(&mut self, immediate: u8) {
-        if self.register.carry_flag() == 0 {
-            self.register.set_pc(immediate);
+    pub(crate) fn branch(&mut self, taken: bool, immediate: u8) {
+        if taken {
+            self.register.set_pc(self.jump_target(immediate));
         }
         self.register.set_carry_flag(0);
     }
 
-    fn in_a(&mut self) {
-        let input = self.port.input();
+    // the switches and the lines mapped devices drive
+    fn input(&self) -> u8 {
+        (self.port.input() | self.memory_map.device_input()) & 0x0f
+    }
+
+    pub(crate) fn in_a(&mut self) {
+        let input = self.input();
         self.register.set_register_a(input);
         self.register.set_carry_flag(0);
     }
 
-    fn in_b(&mut self) {
-        let input = self.port.input();
+    pub(crate) fn in_b(&mut self) {
+        let input = self.input();
         self.register.set_register_b(input);
         self.register.set_carry_flag(0);
     }
 
-    fn out_b(&mut self) {
+    pub(crate) fn out_b(&mut self) -> Result<(), EmulatorErr> {
         let register_b = self.register.register_b();
-        self.port.set_output(register_b);
+        if self.write_output(register_b)? {
+            println!("port (B) output: {}", self.port.output());
+        }
         self.register.set_carry_flag(0);
-        println!("port (B) output: {}", self.port.output());
+        Ok(())
     }
 
-    fn out_im(&mut self, immediate: u8) {
-        self.port.set_output(immediate);
+    pub(crate) fn out_im(&mut self, immediate: u8) -> Result<(), EmulatorErr> {
+        if self.write_output(immediate)? {
+            println!("Output: {}", self.port.output());
+        }
         self.register.set_carry_flag(0);
-        println!("Output: {}", self.port.output());
+        Ok(())
     }
 }
 
@@ -184,18 +499,235 @@ impl CPUemulator {
 #[cfg(test)]
 mod executor_tests{
     use crate::emulator::{
-        executor::CPUemulator,
+        executor::{CPUemulator, HaltReason},
         adapter::Port,
         adapter::Rom,
         register::Register,
+        machine::MachineConfig,
+        memory_map::Device,
+        source_map::{SourceLocation, SourceMap},
+        timer::{Timer, TIMER_CONTROL, TIMER_REGISTERS, TIMER_RELOAD},
     };
+    use crate::EmulatorErr;
+
+    #[test]
+    fn test_rom_overflow() {
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b00000001; 17));
+        let result = CPUemulator::new(register, port, rom);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_pc_out_of_range() {
+        let mut register = Register::new();
+        register.set_pc(16);
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b00000001));
+        let result = CPUemulator::new(register, port, rom);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_large_rom() {
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b01010001; 300));
+        let config = MachineConfig::new(4096, 12).unwrap();
+        let mut emu = CPUemulator::with_config(config, register, port, rom).unwrap();
+        let result = emu.execute();
+        assert!(result.is_ok());
+        assert_eq!(emu.register.register_b(), (300 % 16) as u8);
+        assert_eq!(emu.register.pc(), 300);
+    }
+
+    #[test]
+    fn test_decode_error_location() {
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let mut source_map = SourceMap::new();
+        source_map.insert(1, SourceLocation::new("test.sasm", 2, 1));
+        let rom = Rom::with_source_map(vec!(0b00110001, 0b10001000, 0b00001111), source_map);
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        let err = emu.execute().unwrap_err();
+        assert_eq!(err.msg(), "test.sasm:2:1: No match instruction");
+    }
+
+    #[test]
+    fn test_decode_error_address() {
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b10001000, 0b00001111));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        let err = emu.execute().unwrap_err();
+        assert_eq!(err.msg(), "address 0: No match instruction");
+    }
+
+    fn ram_emulator(rom: Vec<u8>) -> CPUemulator {
+        let mut config = MachineConfig::td4();
+        config.set_ram_size(16);
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        CPUemulator::with_config(config, register, port, Rom::new(rom)).unwrap()
+    }
+
+    #[test]
+    fn test_st_ld_direct() {
+        // mov A 0101, st A [0011], mov A 0000, ld A [0011]
+        let mut emu = ram_emulator(vec!(0b00110101, 0b10000001, 0b00000011, 0b00110000, 0b10000000, 0b00000011));
+        let result = emu.execute();
+        assert!(result.is_ok());
+        assert_eq!(emu.register.register_a(), 0b0101);
+        assert_eq!(emu.ram.read(3).unwrap(), 0b0101);
+        assert_eq!(emu.register.pc(), 6);
+        assert_eq!(emu.snapshot().ram[3], 0b0101);
+    }
+
+    #[test]
+    fn test_st_ld_index_b() {
+        // mov A 0111, mov B 0010, st A [B], mov A 0000, ld A [B]
+        let mut emu = ram_emulator(vec!(0b00110111, 0b01110010, 0b10000011, 0b00110000, 0b10000010));
+        let result = emu.execute();
+        assert!(result.is_ok());
+        assert_eq!(emu.register.register_a(), 0b0111);
+        assert_eq!(emu.ram.read(2).unwrap(), 0b0111);
+        assert_eq!(emu.register.pc(), 5);
+    }
+
+    #[test]
+    fn test_ld_without_ram() {
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b10000010));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        let err = emu.execute().unwrap_err();
+        assert_eq!(err.msg(), "address 0: Memory address 0 is not mapped");
+    }
+
+    struct Latch {
+        value: u8,
+    }
+
+    impl Device for Latch {
+        fn name(&self) -> &str {
+            "latch"
+        }
+
+        fn read(&mut self, offset: usize) -> Result<u8, EmulatorErr> {
+            Ok(self.value + offset as u8)
+        }
+
+        fn write(&mut self, _offset: usize, value: u8) -> Result<(), EmulatorErr> {
+            self.value = value;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_memory_map_dispatch() {
+        // mov A 0011, st A [00100000], ld A [00100001], st A [11111111], ld A [01000000]
+        let mut emu = ram_emulator(vec!(
+            0b00110011, 0b10000001, 0b00100000, 0b10000000, 0b00100001,
+            0b10000001, 0b11111111, 0b10000000, 0b01000000,
+        ));
+        emu.memory_map.map_device(0b00100000, 2, Box::new(Latch { value: 0 })).unwrap();
+        emu.memory_map.map_port(0b11111111).unwrap();
+        emu.memory_map.map_rom(0b01000000, 16, 0).unwrap();
+        let result = emu.execute();
+        assert!(result.is_ok());
+        assert_eq!(emu.port.output(), 0b0100);
+        assert_eq!(emu.register.register_a(), 0b0011);
+    }
+
+    #[test]
+    fn test_load_port_input() {
+        // ld A [11111111] reads the switches and the device lines like in A
+        let mut emu = ram_emulator(vec!(0b10000000, 0b11111111));
+        let mut timer = Timer::new();
+        timer.write(TIMER_RELOAD, 0b1111).unwrap();
+        timer.write(TIMER_CONTROL, 1).unwrap();
+        timer.tick();
+        emu.port = Port::new(0b0001, 0b0000);
+        emu.memory_map.map_device(0b00100000, TIMER_REGISTERS, Box::new(timer)).unwrap();
+        emu.memory_map.map_port(0b11111111).unwrap();
+        emu.execute().unwrap();
+        assert_eq!(emu.register.register_a(), 0b1001);
+    }
+
+    #[test]
+    fn test_store_to_rom() {
+        let mut emu = ram_emulator(vec!(0b10000001, 0b01000000));
+        emu.memory_map.map_rom(0b01000000, 16, 0).unwrap();
+        let err = emu.execute().unwrap_err();
+        assert_eq!(err.msg(), "address 0: Memory address 64 is read only rom");
+    }
+
+    #[test]
+    fn test_ld_missing_operand() {
+        let mut emu = ram_emulator(vec!(0b10000000));
+        let err = emu.execute().unwrap_err();
+        assert_eq!(err.msg(), "address 0: Missing operand word");
+    }
+
+    fn banked_emulator(rom: Vec<u8>) -> CPUemulator {
+        let mut config = MachineConfig::new(64, 6).unwrap();
+        config.set_rom_banks(4);
+        config.set_bank_port_base(Some(0b1100));
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        CPUemulator::with_config(config, register, port, Rom::new(rom)).unwrap()
+    }
+
+    #[test]
+    fn test_bank_jump() {
+        // bank 0: bank 0010, jmp 0001 / bank 2: mov A 0000, mov A 0111
+        let mut rom = vec!(0b10000100, 0b00000010, 0b11110001);
+        rom.resize(33, 0);
+        rom.push(0b00110111);
+        let mut emu = banked_emulator(rom);
+        let result = emu.execute();
+        assert!(result.is_ok());
+        assert_eq!(emu.register.bank(), 2);
+        assert_eq!(emu.register.register_a(), 0b0111);
+        assert_eq!(emu.register.pc(), 34);
+        assert_eq!(emu.rom.banks(), 3);
+        assert_eq!(emu.rom.bank(2), &[0b00000000, 0b00110111]);
+    }
+
+    #[test]
+    fn test_bank_port_select() {
+        // out 1101 selects bank 1, jmp 0000 / bank 1: out 0011
+        let mut rom = vec!(0b10111101, 0b11110000);
+        rom.resize(16, 0);
+        rom.push(0b10110011);
+        let mut emu = banked_emulator(rom);
+        let result = emu.execute();
+        assert!(result.is_ok());
+        assert_eq!(emu.register.bank(), 1);
+        assert_eq!(emu.port.output(), 0b0011);
+        assert_eq!(emu.register.pc(), 17);
+    }
+
+    #[test]
+    fn test_bank_out_of_range() {
+        let mut emu = banked_emulator(vec!(0b10000100, 0b00000100));
+        let err = emu.execute().unwrap_err();
+        assert_eq!(err.msg(), "address 0: Rom bank 4 out of range: machine has 4 banks");
+
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b10000100, 0b00000000));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        assert!(emu.execute().is_err());
+    }
 
     #[test]
     fn test_add_a() {
         let register = Register::new();
         let port = Port::new(0b0000, 0b0000);
         let rom = Rom::new(vec!(0b00000001));
-        let mut emu = CPUemulator::new(register, port, rom);
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
         let result = emu.execute();
         assert!(result.is_ok());
         assert_eq!(emu.register.register_a(), 1);
@@ -209,7 +741,7 @@ mod executor_tests{
         let register = Register::new();
         let port = Port::new(0b0000, 0b0000);
         let rom = Rom::new(vec!(0b01010001));
-        let mut emu = CPUemulator::new(register, port, rom);
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
         let result = emu.execute();
         assert!(result.is_ok());
         assert_eq!(emu.register.register_a(), 0);
@@ -223,7 +755,7 @@ mod executor_tests{
         let register = Register::new();
         let port = Port::new(0b0000, 0b0000);
         let rom = Rom::new(vec!(0b00110001));
-        let mut emu = CPUemulator::new(register, port, rom);
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
         let result = emu.execute();
         assert!(result.is_ok());
         assert_eq!(emu.register.register_a(), 1);
@@ -237,7 +769,7 @@ mod executor_tests{
         let register = Register::new();
         let port = Port::new(0b0000, 0b0000);
         let rom = Rom::new(vec!(0b01110001));
-        let mut emu = CPUemulator::new(register, port, rom);
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
         let result = emu.execute();
         assert!(result.is_ok());
         assert_eq!(emu.register.register_a(), 0);
@@ -252,7 +784,7 @@ mod executor_tests{
         register.set_register_b(2);
         let port = Port::new(0b0000, 0b0000);
         let rom = Rom::new(vec!(0b00010000));
-        let mut emu = CPUemulator::new(register, port, rom);
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
         let result = emu.execute();
         assert!(result.is_ok());
         assert_eq!(emu.register.register_a(), 2);
@@ -267,7 +799,7 @@ mod executor_tests{
         register.set_register_a(2);
         let port = Port::new(0b0000, 0b0000);
         let rom = Rom::new(vec!(0b01000000));
-        let mut emu = CPUemulator::new(register, port, rom);
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
         let result = emu.execute();
         assert!(result.is_ok());
         assert_eq!(emu.register.register_a(), 2);
@@ -281,7 +813,7 @@ mod executor_tests{
         let register = Register::new();
         let port = Port::new(0b0000, 0b0000);
         let rom = Rom::new(vec!(0b11110010, 0b00110001, 0b01110010));
-        let mut emu = CPUemulator::new(register, port, rom);
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
         let result = emu.execute();
         assert!(result.is_ok());
         assert_eq!(emu.register.register_a(), 0);
@@ -294,7 +826,7 @@ mod executor_tests{
         let register = Register::new();
         let port = Port::new(0b0000, 0b0000);
         let rom = Rom::new(vec!(0b11110010, 0b00110001, 0b01110010));
-        let mut emu = CPUemulator::new(register, port, rom);
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
         let result = emu.execute();
         assert!(result.is_ok());
         assert_eq!(emu.register.register_a(), 0);
@@ -302,12 +834,448 @@ mod executor_tests{
         assert_eq!(emu.register.pc(), 3);
     }
 
+    #[test]
+    fn test_add_zero_flag() {
+        // mov A 1111, add A 0001 -> 0 with carry and zero
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b00111111, 0b00000001));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_a(), 0);
+        assert_eq!(emu.register.carry_flag(), 1);
+        assert_eq!(emu.register.zero_flag(), 1);
+
+        // add B 0001 -> 1 clears both flags again
+        let register = emu.register.clone();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b00111111, 0b00000001, 0b01010001));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        emu.register.set_pc(2);
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.carry_flag(), 0);
+        assert_eq!(emu.register.zero_flag(), 0);
+    }
+
+    #[test]
+    fn test_sub_borrow_at_zero() {
+        // sub A 0001 from 0 wraps to 1111 and borrows
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b10100001));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_a(), 0b1111);
+        assert_eq!(emu.register.carry_flag(), 1);
+        assert_eq!(emu.register.zero_flag(), 0);
+
+        // sub B 0000 from 0 neither borrows nor leaves a non zero result
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b10000101, 0b00000000));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_b(), 0);
+        assert_eq!(emu.register.carry_flag(), 0);
+        assert_eq!(emu.register.zero_flag(), 1);
+    }
+
+    #[test]
+    fn test_sub_b() {
+        let mut register = Register::new();
+        register.set_register_b(0b0101);
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b10000101, 0b00000011));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_b(), 0b0010);
+        assert_eq!(emu.register.carry_flag(), 0);
+        assert_eq!(emu.register.pc(), 2);
+    }
+
+    #[test]
+    fn test_cmp() {
+        // cmp A 0011 with A = 0011: equal, no borrow, A unchanged
+        let mut register = Register::new();
+        register.set_register_a(0b0011);
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b10000110, 0b00000011));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_a(), 0b0011);
+        assert_eq!(emu.register.carry_flag(), 0);
+        assert_eq!(emu.register.zero_flag(), 1);
+
+        // cmp B 0001 with B = 0: borrow, jnc falls through
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b10000111, 0b00000001, 0b11100100, 0b00110001));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_a(), 0b0001);
+        assert_eq!(emu.register.register_b(), 0);
+    }
+
+    fn alu_emulator(a: u8, b: u8, carry: u8, rom: Vec<u8>) -> CPUemulator {
+        let mut config = MachineConfig::td4();
+        config.set_extended_alu(true);
+        let mut register = Register::new();
+        register.set_register_a(a);
+        register.set_register_b(b);
+        register.set_carry_flag(carry);
+        let port = Port::new(0b0000, 0b0000);
+        CPUemulator::with_config(config, register, port, Rom::new(rom)).unwrap()
+    }
+
+    #[test]
+    fn test_alu_logic() {
+        // and, or, xor A B; not B
+        let cases = [
+            (0b00000001, 0b1100 & 0b1010),
+            (0b00000010, 0b1100 | 0b1010),
+            (0b00000011, 0b1100 ^ 0b1010),
+        ];
+        for (function, expected) in cases {
+            let mut emu = alu_emulator(0b1100, 0b1010, 1, vec!(0b10001000, function));
+            assert!(emu.execute().is_ok());
+            assert_eq!(emu.register.register_a(), expected);
+            assert_eq!(emu.register.carry_flag(), 0);
+        }
+
+        let mut emu = alu_emulator(0b0000, 0b1111, 0, vec!(0b10001000, 0b00010100));
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_b(), 0);
+        assert_eq!(emu.register.zero_flag(), 1);
+    }
+
+    #[test]
+    fn test_alu_add_registers() {
+        // add B A with carry out
+        let mut emu = alu_emulator(0b1001, 0b1000, 0, vec!(0b10001000, 0b00010000));
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_a(), 0b1001);
+        assert_eq!(emu.register.register_b(), 0b0001);
+        assert_eq!(emu.register.carry_flag(), 1);
+        assert_eq!(emu.register.pc(), 2);
+    }
+
+    #[test]
+    fn test_alu_shift_rotate() {
+        // shl A: carry takes bit 3
+        let mut emu = alu_emulator(0b1001, 0, 0, vec!(0b10001000, 0b00000101));
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_a(), 0b0010);
+        assert_eq!(emu.register.carry_flag(), 1);
+
+        // shr A: carry takes bit 0
+        let mut emu = alu_emulator(0b1001, 0, 0, vec!(0b10001000, 0b00000110));
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_a(), 0b0100);
+        assert_eq!(emu.register.carry_flag(), 1);
+
+        // rcl A: old carry enters bit 0
+        let mut emu = alu_emulator(0b0100, 0, 1, vec!(0b10001000, 0b00000111));
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_a(), 0b1001);
+        assert_eq!(emu.register.carry_flag(), 0);
+
+        // rcr B: old carry enters bit 3
+        let mut emu = alu_emulator(0, 0b0011, 1, vec!(0b10001000, 0b00011000));
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_b(), 0b1001);
+        assert_eq!(emu.register.carry_flag(), 1);
+    }
+
+    #[test]
+    fn test_alu_disabled() {
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b10001000, 0b00000001));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        let err = emu.execute().unwrap_err();
+        assert_eq!(err.msg(), "address 0: Extended alu instructions are not enabled on this machine");
+    }
+
+    fn stack_emulator(depth: usize, wrap: bool, rom: Vec<u8>) -> CPUemulator {
+        let mut config = MachineConfig::td4();
+        config.set_stack_depth(depth);
+        config.set_stack_wrap(wrap);
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        CPUemulator::with_config(config, register, port, Rom::new(rom)).unwrap()
+    }
+
+    #[test]
+    fn test_call_ret() {
+        // call 0101 twice, jmp to end / 0101: add A 0001, ret
+        let mut emu = stack_emulator(4, false, vec!(
+            0b10001001, 0b00000101, 0b10001001, 0b00000101, 0b11110111,
+            0b00000001, 0b10001010,
+        ));
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_a(), 2);
+        assert!(emu.register.stack().is_empty());
+        assert_eq!(emu.register.pc(), 7);
+    }
+
+    #[test]
+    fn test_stack_overflow_underflow() {
+        // 0000: call 0000 recurses until the stack is full
+        let mut emu = stack_emulator(2, false, vec!(0b10001001, 0b00000000));
+        let err = emu.execute().unwrap_err();
+        assert_eq!(err.msg(), "address 0: Stack overflow: depth is 2");
+        assert_eq!(emu.snapshot().stack, vec!(2, 2));
+
+        let mut emu = stack_emulator(2, false, vec!(0b10001010));
+        let err = emu.execute().unwrap_err();
+        assert_eq!(err.msg(), "address 0: Stack underflow: ret without call");
+
+        let mut emu = stack_emulator(0, false, vec!(0b10001001, 0b00000000));
+        assert!(emu.execute().is_err());
+
+        let config = MachineConfig::new(4096, 12).unwrap();
+        let rom = Rom::new(vec!(0b10001001, 0b00000010, 0b10001010));
+        let mut emu = CPUemulator::with_config(config, Register::new(), Port::new(0b0000, 0b0000), rom).unwrap();
+        let err = emu.execute().unwrap_err();
+        assert_eq!(err.msg(), "address 0: call takes an 8 bit address, this machine has 12 bit addresses");
+    }
+
+    #[test]
+    fn test_stack_wrap() {
+        // jmp 1111 / 0010: call 0101 / 0101: call 1000, ret / 1000: add A 0001, ret
+        let mut emu = stack_emulator(1, true, vec!(
+            0b11111111, 0b00000000, 0b10001001, 0b00000101, 0b00000000,
+            0b10001001, 0b00001000, 0b10001010, 0b00000001, 0b10001010,
+        ));
+        emu.register.set_pc(2);
+        assert!(emu.execute().is_ok());
+        // the inner call dropped the outer return address, so the second ret found the
+        // stack empty and went to 0, where jmp 1111 leaves the program
+        assert_eq!(emu.register.register_a(), 1);
+        assert!(emu.register.stack().is_empty());
+        assert_eq!(emu.register.pc(), 15);
+    }
+
+    #[test]
+    fn test_hlt_nop() {
+        // mov A 1111, add A 0001, nop, hlt, mov B 0001
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b00111111, 0b00000001, 0b10001100, 0b10001011, 0b01110001));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        assert_eq!(emu.execute().unwrap(), HaltReason::Hlt);
+        assert_eq!(emu.register.register_b(), 0);
+        assert_eq!(emu.register.pc(), 4);
+        // nop keeps the flags the add left
+        assert_eq!(emu.register.carry_flag(), 1);
+        assert_eq!(emu.register.zero_flag(), 1);
+        assert_eq!(emu.snapshot().halted, Some(HaltReason::Hlt));
+
+        // a halted machine stays put until the state is cleared
+        assert_eq!(emu.execute().unwrap(), HaltReason::Hlt);
+        assert_eq!(emu.register.register_b(), 0);
+        emu.halted = None;
+        assert_eq!(emu.execute().unwrap(), HaltReason::EndOfRom);
+        assert_eq!(emu.register.register_b(), 1);
+    }
+
+    #[test]
+    fn test_end_of_rom() {
+        // nothing past the end of rom runs, not even the first word of an empty rom
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let mut emu = CPUemulator::new(register, port, Rom::new(vec!())).unwrap();
+        assert_eq!(emu.execute().unwrap(), HaltReason::EndOfRom);
+        assert_eq!(emu.register.pc(), 0);
+    }
+
+    #[test]
+    fn test_interrupt_wakes_hlt() {
+        // jmp 0100 / 0001: add B 0001, reti, nop / 0100: ei, hlt, out B
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(
+            0b11110100, 0b01010001, 0b10001101, 0b10001100, 0b10001110, 0b10001011, 0b10010000,
+        ));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        assert_eq!(emu.execute().unwrap(), HaltReason::Hlt);
+
+        // the same input again is no change and raises nothing
+        emu.set_input(0b0000);
+        assert!(!emu.interrupt_pending());
+        assert_eq!(emu.execute().unwrap(), HaltReason::Hlt);
+
+        emu.set_input(0b0001);
+        assert!(emu.interrupt_pending());
+        assert_eq!(emu.execute().unwrap(), HaltReason::EndOfRom);
+        assert!(!emu.interrupt_pending());
+        assert_eq!(emu.port.output(), 0b0001);
+        assert_eq!(emu.register.interrupt_enable(), 1);
+    }
+
+    // raises the interrupt line on the nth instruction boundary
+    struct Alarm {
+        polls: usize,
+        at: usize,
+    }
+
+    impl Device for Alarm {
+        fn name(&self) -> &str {
+            "alarm"
+        }
+
+        fn read(&mut self, _offset: usize) -> Result<u8, EmulatorErr> {
+            Ok(0)
+        }
+
+        fn write(&mut self, _offset: usize, _value: u8) -> Result<(), EmulatorErr> {
+            Ok(())
+        }
+
+        fn interrupt(&mut self) -> bool {
+            self.polls += 1;
+            self.polls == self.at
+        }
+    }
+
+    #[test]
+    fn test_interrupt_saves_carry() {
+        // jmp 0100 / 0001: add B 0001, reti, nop / 0100: ei, mov A 1111, add A 0001, nop
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(
+            0b11110100, 0b01010001, 0b10001101, 0b10001100,
+            0b10001110, 0b00111111, 0b00000001, 0b10001100,
+        ));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        // the fifth boundary is the one before the last nop
+        emu.memory_map.map_device(0, 1, Box::new(Alarm { polls: 0, at: 5 })).unwrap();
+        assert_eq!(emu.execute().unwrap(), HaltReason::EndOfRom);
+        assert_eq!(emu.register.register_b(), 1);
+        // add B in the handler cleared carry, reti put back the carry of add A
+        assert_eq!(emu.register.carry_flag(), 1);
+    }
+
+    #[test]
+    fn test_timer_poll() {
+        // mov A 0001, st A [00000010] / 0011: add B 0001, in A, cmp A 1000, jnz 0011 / out 0001
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(
+            0b00110001, 0b10000001, 0b00000010, 0b01010001, 0b00100000,
+            0b10000110, 0b00001000, 0b11010011, 0b10110001,
+        ));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        emu.memory_map.map_device(0, TIMER_REGISTERS, Box::new(Timer::new())).unwrap();
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.port.output(), 0b0001);
+        // st leaves the count at 1, 15 more instructions overflow it: the fifth in A sees the flag
+        assert_eq!(emu.register.register_b(), 5);
+        assert_eq!(emu.snapshot().input, 0b1000);
+    }
+
+    #[test]
+    fn test_timer_input_mask() {
+        // mov A 1110, st A [00000000]: reload 1110 / mov A 0001, st A [00000010]: run / add A 0000, out 0101
+        let rom = || Rom::new(vec!(
+            0b00111110, 0b10000001, 0b00000000, 0b00110001, 0b10000001, 0b00000010,
+            0b00000000, 0b10110101,
+        ));
+        let mut emu = CPUemulator::new(Register::new(), Port::new(0b0000, 0b0000), rom()).unwrap();
+        emu.memory_map.map_device(0, TIMER_REGISTERS, Box::new(Timer::new())).unwrap();
+        emu.execute().unwrap();
+        assert_eq!(emu.port.output(), 0b0101);
+        assert_eq!(emu.snapshot().input, 0b1000);
+
+        let mut timer = Timer::new();
+        timer.set_input_mask(0);
+        let mut emu = CPUemulator::new(Register::new(), Port::new(0b0000, 0b0000), rom()).unwrap();
+        emu.memory_map.map_device(0, TIMER_REGISTERS, Box::new(timer)).unwrap();
+        emu.execute().unwrap();
+        assert_eq!(emu.snapshot().input, 0);
+    }
+
+    #[test]
+    fn test_nested_interrupt() {
+        // ei / 0001: ei, hlt with a timer that overflows after every instruction
+        let mut timer = Timer::new();
+        timer.write(TIMER_RELOAD, 0b1111).unwrap();
+        timer.write(TIMER_CONTROL, 1).unwrap();
+        let rom = Rom::new(vec!(0b10001110, 0b10001110, 0b10001011));
+        let mut emu = CPUemulator::new(Register::new(), Port::new(0b0000, 0b0000), rom).unwrap();
+        emu.memory_map.map_device(0, TIMER_REGISTERS, Box::new(timer)).unwrap();
+        let err = emu.execute().unwrap_err();
+        assert_eq!(err.msg(), "address 2: Nested interrupt: the handler has not returned with reti");
+    }
+
+    #[test]
+    fn test_interrupt_disabled() {
+        // di, mov B 0001
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b10001111, 0b01110001));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        emu.raise_interrupt();
+        assert_eq!(emu.execute().unwrap(), HaltReason::EndOfRom);
+        assert_eq!(emu.register.register_b(), 1);
+        assert!(emu.interrupt_pending());
+        assert!(emu.snapshot().interrupt_pending);
+
+        // reti
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let mut emu = CPUemulator::new(register, port, Rom::new(vec!(0b10001101))).unwrap();
+        let err = emu.execute().unwrap_err();
+        assert_eq!(err.msg(), "address 0: reti outside an interrupt handler");
+    }
+
+    #[test]
+    fn test_jz() {
+        // mov A 1111, add A 0001, jz 0100, mov B 0001, out 0011
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b00111111, 0b00000001, 0b11000100, 0b01110001, 0b10110011));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_b(), 0);
+        assert_eq!(emu.port.output(), 0b0011);
+        // the jump clears carry like every non-add instruction, zero is kept
+        assert_eq!(emu.register.carry_flag(), 0);
+        assert_eq!(emu.register.zero_flag(), 1);
+    }
+
+    #[test]
+    fn test_jnz() {
+        // add A 0001, jnz 0011, mov B 0001, mov A 1111, add A 0001, jnz 0000
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b00000001, 0b11010011, 0b01110001, 0b00111111, 0b00000001, 0b11010000));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.register_b(), 0);
+        assert_eq!(emu.register.register_a(), 0);
+        assert_eq!(emu.register.pc(), 6);
+    }
+
+    #[test]
+    fn test_mov_keeps_zero_flag() {
+        // mov A 1111, add A 0001, mov A 0011, in B, out B, jz 0111, mov B 0001
+        let register = Register::new();
+        let port = Port::new(0b0000, 0b0000);
+        let rom = Rom::new(vec!(0b00111111, 0b00000001, 0b00110011, 0b01100000, 0b10010000, 0b11000111, 0b01110001));
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
+        assert!(emu.execute().is_ok());
+        assert_eq!(emu.register.zero_flag(), 1);
+        assert_eq!(emu.register.register_b(), 0);
+        assert_eq!(emu.register.pc(), 7);
+    }
+
     #[test]
     fn test_in_a() {
         let register = Register::new();
         let port = Port::new(0b0001, 0b0000);
         let rom = Rom::new(vec!(0b00100000));
-        let mut emu = CPUemulator::new(register, port, rom);
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
         let result = emu.execute();
         assert!(result.is_ok());
         assert_eq!(emu.register.register_a(), 1);
@@ -320,7 +1288,7 @@ mod executor_tests{
         let register = Register::new();
         let port = Port::new(0b0010, 0b0000);
         let rom = Rom::new(vec!(0b01100000));
-        let mut emu = CPUemulator::new(register, port, rom);
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
         let result = emu.execute();
         assert!(result.is_ok());
         assert_eq!(emu.register.register_a(), 0);
@@ -333,7 +1301,7 @@ mod executor_tests{
         let register = Register::new();
         let port = Port::new(0b0000, 0b0000);
         let rom = Rom::new(vec!(0b10010000));
-        let mut emu = CPUemulator::new(register, port, rom);
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
         let result = emu.execute();
         assert!(result.is_ok());
         assert_eq!(emu.register.register_a(), 0);
@@ -346,7 +1314,7 @@ mod executor_tests{
         let register = Register::new();
         let port = Port::new(0b0000, 0b0000);
         let rom = Rom::new(vec!(0b10110000));
-        let mut emu = CPUemulator::new(register, port, rom);
+        let mut emu = CPUemulator::new(register, port, rom).unwrap();
         let result = emu.execute();
         assert!(result.is_ok());
         assert_eq!(emu.register.register_a(), 0);
@@ -0,0 +1,249 @@
+use crate::EmulatorErr;
+
+// constant expressions in operands and directives
+//   literals: 0b0101, 0x5 and plain 0101, which is binary like everywhere else in .sasm
+//   names:    constants and labels, their values come from the caller
+//   operators, loosest first: == != < <= > >=  |  ^  &  << >>  + -  * / %, unary - ~ +,
+//             parentheses; comparisons give 1 or 0
+
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+const OPERATORS: [&str; 17] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "<", ">", "+", "-", "*", "/", "%", "&", "|", "^", "~",
+];
+
+// binary operators by precedence level, loosest first
+const LEVELS: [&[&str]; 7] = [
+    &["==", "!=", "<", "<=", ">", ">="],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+// value of text, symbol gives the value of a name
+pub fn evaluate(text: &str, symbol: &dyn Fn(&str) -> Option<i64>) -> Result<i64, EmulatorErr> {
+    let mut expression = Expression { items: lex(text)?, position: 0, symbol };
+    let value = expression.binary(0)?;
+    match expression.items.get(expression.position) {
+        None => Ok(value),
+        Some(_) => Err(EmulatorErr::new(&format!("unexpected {} in expression", expression.describe()))),
+    }
+}
+
+// names text refers to, in order of appearance
+pub fn names(text: &str) -> Result<Vec<String>, EmulatorErr> {
+    Ok(lex(text)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::Name(name) => Some(name),
+            _ => None,
+        })
+        .collect())
+}
+
+fn lex(text: &str) -> Result<Vec<Item>, EmulatorErr> {
+    let mut items = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let word_len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        if c.is_ascii_digit() {
+            items.push(Item::Number(number(&rest[..word_len])?));
+            rest = &rest[word_len..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            items.push(Item::Name(rest[..word_len].to_string()));
+            rest = &rest[word_len..];
+        } else if c == '(' || c == ')' {
+            items.push(if c == '(' { Item::Open } else { Item::Close });
+            rest = &rest[1..];
+        } else {
+            let op = OPERATORS.iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| EmulatorErr::new(&format!("unexpected {} in expression", c)))?;
+            items.push(Item::Op(op));
+            rest = &rest[op.len()..];
+        }
+    }
+
+    Ok(items)
+}
+
+fn number(text: &str) -> Result<i64, EmulatorErr> {
+    let lower = text.to_lowercase();
+    if let Some(digits) = lower.strip_prefix("0x") {
+        i64::from_str_radix(digits, 16).map_err(|_| EmulatorErr::new(&format!("invalid hex number {}", text)))
+    } else if let Some(digits) = lower.strip_prefix("0b") {
+        i64::from_str_radix(digits, 2).map_err(|_| EmulatorErr::new(&format!("invalid binary number {}", text)))
+    } else {
+        i64::from_str_radix(text, 2).map_err(|_| EmulatorErr::new("From binary to decimal failed"))
+    }
+}
+
+struct Expression<'a> {
+    items: Vec<Item>,
+    position: usize,
+    symbol: &'a dyn Fn(&str) -> Option<i64>,
+}
+
+impl Expression<'_> {
+    fn binary(&mut self, level: usize) -> Result<i64, EmulatorErr> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut value = self.binary(level + 1)?;
+        while let Some(Item::Op(op)) = self.items.get(self.position) {
+            let op = *op;
+            if !LEVELS[level].contains(&op) {
+                break;
+            }
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            value = apply(op, value, right)?;
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, EmulatorErr> {
+        let item = self.items.get(self.position).cloned();
+        self.position += 1;
+
+        match item {
+            Some(Item::Op("-")) => self.unary()?.checked_neg().ok_or_else(overflow),
+            Some(Item::Op("~")) => Ok(!self.unary()?),
+            Some(Item::Op("+")) => self.unary(),
+            Some(Item::Number(value)) => Ok(value),
+            Some(Item::Name(name)) => (self.symbol)(&name)
+                .ok_or_else(|| EmulatorErr::new(&format!("undefined symbol {}", name))),
+            Some(Item::Open) => {
+                let value = self.binary(0)?;
+                if self.items.get(self.position) != Some(&Item::Close) {
+                    return Err(EmulatorErr::new("missing ) in expression"));
+                }
+                self.position += 1;
+                Ok(value)
+            },
+            _ => {
+                self.position -= 1;
+                Err(EmulatorErr::new(&format!("unexpected {} in expression", self.describe())))
+            },
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.items.get(self.position) {
+            Some(Item::Number(value)) => value.to_string(),
+            Some(Item::Name(name)) => name.clone(),
+            Some(Item::Op(op)) => op.to_string(),
+            Some(Item::Open) => "(".to_string(),
+            Some(Item::Close) => ")".to_string(),
+            None => "end".to_string(),
+        }
+    }
+}
+
+fn apply(op: &str, left: i64, right: i64) -> Result<i64, EmulatorErr> {
+    match op {
+        "+" => left.checked_add(right).ok_or_else(overflow),
+        "-" => left.checked_sub(right).ok_or_else(overflow),
+        "*" => left.checked_mul(right).ok_or_else(overflow),
+        "/" | "%" if right == 0 => Err(EmulatorErr::new("division by zero in expression")),
+        "/" => left.checked_div(right).ok_or_else(overflow),
+        "%" => left.checked_rem(right).ok_or_else(overflow),
+        "&" => Ok(left & right),
+        "|" => Ok(left | right),
+        "^" => Ok(left ^ right),
+        "==" => Ok((left == right) as i64),
+        "!=" => Ok((left != right) as i64),
+        "<" => Ok((left < right) as i64),
+        "<=" => Ok((left <= right) as i64),
+        ">" => Ok((left > right) as i64),
+        ">=" => Ok((left >= right) as i64),
+        "<<" | ">>" if !(0..64).contains(&right) => {
+            Err(EmulatorErr::new(&format!("shift by {} in expression", right)))
+        },
+        "<<" => Ok(left << right),
+        _ => Ok(left >> right),
+    }
+}
+
+fn overflow() -> EmulatorErr {
+    EmulatorErr::new("expression overflows")
+}
+
+
+#[cfg(test)]
+mod expression_tests {
+    use crate::emulator::expression::{evaluate, names};
+
+    fn symbol(name: &str) -> Option<i64> {
+        match name {
+            "N" => Some(0),
+            "PATTERN" => Some(0b0101),
+            "loop" => Some(3),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn evaluate_test() {
+        let cases = [
+            ("0101", 0b0101),
+            ("0b0101", 0b0101),
+            ("0xF", 15),
+            ("loop+1", 4),
+            ("PATTERN ^ 0b1111", 0b1010),
+            ("(N-1)&0xF", 15),
+            ("~PATTERN & 0xf", 0b1010),
+            ("1 + 10 * 11", 7),
+            ("(1 + 10) * 11", 9),
+            ("1 << 11 >> 1", 4),
+            ("0x10 / 11 % 11", 2),
+            ("1 | 10 ^ 11 & 10", 1),
+            ("-1", -1),
+            ("PATTERN == 0101", 1),
+            ("PATTERN & 1 != 0", 1),
+            ("loop >= 100", 0),
+            ("loop < 100 == 1", 1),
+        ];
+
+        for (text, value) in cases {
+            assert_eq!(evaluate(text, &symbol).unwrap(), value, "{}", text);
+        }
+    }
+
+    #[test]
+    fn evaluate_error_test() {
+        let cases = [
+            ("0002", "From binary to decimal failed"),
+            ("0xG", "invalid hex number 0xG"),
+            ("other + 1", "undefined symbol other"),
+            ("(1 + 1", "missing ) in expression"),
+            ("1 +", "unexpected end in expression"),
+            ("1 1", "unexpected 1 in expression"),
+            ("1 / N", "division by zero in expression"),
+            ("1 << 1000000", "shift by 64 in expression"),
+            ("1 $ 1", "unexpected $ in expression"),
+        ];
+
+        for (text, message) in cases {
+            assert_eq!(evaluate(text, &symbol).unwrap_err().msg(), message, "{}", text);
+        }
+        assert_eq!(names("loop + (N & 1)").unwrap(), vec!["loop".to_string(), "N".to_string()]);
+    }
+}
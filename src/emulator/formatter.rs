@@ -0,0 +1,296 @@
+use crate::emulator::expression;
+use crate::emulator::isa::{Isa, Operand};
+use crate::emulator::parser::{line_words, Dialect, LineWord, Parser};
+use crate::emulator::td4::Td4;
+use crate::EmulatorErr;
+
+// column code starts in after a label, and the width mnemonics are padded to
+pub const CODE_INDENT: usize = 8;
+pub const MNEMONIC_WIDTH: usize = 5;
+// column trailing comments line up at
+pub const COMMENT_COLUMN: usize = 33;
+
+// directives that place words in rom sit in the code column, the others at the start of the line
+const DATA_DIRECTIVES: [&str; 2] = [".byte", ".fill"];
+// directives whose numbers are rom words or addresses, written with 8 digits
+const WIDE_DIRECTIVES: [&str; 4] = [".byte", ".fill", ".org", ".align"];
+
+// .sasm source in the canonical layout: labels in the first column, code in
+// the code column with aligned operands, lowercase mnemonics, binary
+// literals as wide as their operand field and trailing comments lined up
+#[derive(Debug)]
+pub struct Formatter<I: Isa = Td4> {
+    isa: I,
+}
+
+impl Formatter {
+    pub fn new() -> Formatter {
+        Formatter::with_isa(Td4::new())
+    }
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I: Isa> Formatter<I> {
+    pub fn with_isa(isa: I) -> Self {
+        Formatter { isa }
+    }
+
+    // parser holds source with the dialect, include paths and defines it is
+    // built with; source that does not parse is not rewritten. The book
+    // dialect gets uppercase mnemonics and comma separated operands, runs of
+    // blank lines become one and the text ends with a single newline
+    pub fn format(&self, parser: &mut Parser<I>, source: &str) -> Result<String, EmulatorErr> {
+        parser.parse()?;
+        let dialect = parser.dialect();
+
+        let mut lines: Vec<String> = Vec::new();
+        for line in source.lines() {
+            let line = self.line(dialect, line);
+            if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+                continue;
+            }
+            lines.push(line);
+        }
+        while lines.last().is_some_and(|last| last.is_empty()) {
+            lines.pop();
+        }
+
+        Ok(lines.iter().map(|line| format!("{}\n", line)).collect())
+    }
+
+    fn line(&self, dialect: Dialect, line: &str) -> String {
+        let (words, comment) = line_words(line);
+        let comment = comment.map(|(text, column)| {
+            let text = text.trim();
+            let separator = if text.is_empty() || text.starts_with(';') { "" } else { " " };
+            (format!(";{}{}", separator, text), column)
+        });
+
+        match (self.code(dialect, line, &words), comment) {
+            (None, None) => String::new(),
+            (None, Some((comment, 1))) => comment,
+            (None, Some((comment, _))) => format!("{}{}", " ".repeat(CODE_INDENT), comment),
+            (Some(code), None) => code,
+            (Some(code), Some((comment, _))) => {
+                let width = (COMMENT_COLUMN - 1).max(code.len() + 1);
+                format!("{:<width$}{}", code, comment, width = width)
+            },
+        }
+    }
+
+    // labels and the statement after them
+    fn code(&self, dialect: Dialect, line: &str, words: &[LineWord]) -> Option<String> {
+        let labels = words.iter()
+            .take_while(|(word, _)| word.len() > 1 && word.ends_with(':') && !word.starts_with('.'))
+            .count();
+        let prefix = words[..labels].iter().map(|(word, _)| *word).collect::<Vec<_>>().join(" ");
+        let statement = &words[labels..];
+
+        let Some((first, _)) = statement.first() else {
+            return (!prefix.is_empty()).then_some(prefix);
+        };
+        let directive = first.to_lowercase();
+        let constant = statement.get(1).is_some_and(|(word, _)| *word == "=");
+        let text = if directive.starts_with('.') {
+            let width = if WIDE_DIRECTIVES.contains(&directive.as_str()) { 8 } else { 4 };
+            let operands = literals(&self.verbatim(line, &statement[1..]), width);
+            if operands.is_empty() { directive.clone() } else { format!("{} {}", directive, operands) }
+        } else if constant {
+            literals(&self.verbatim(line, statement), 4)
+        } else {
+            self.instruction(dialect, line, statement)
+        };
+
+        let top_level = constant || (directive.starts_with('.') && !DATA_DIRECTIVES.contains(&directive.as_str()));
+        Some(if top_level && prefix.is_empty() {
+            text
+        } else if prefix.len() < CODE_INDENT {
+            format!("{:<indent$}{}", prefix, text, indent = CODE_INDENT)
+        } else {
+            format!("{} {}", prefix, text)
+        })
+    }
+
+    // words as written; quoted text keeps its spacing
+    fn verbatim(&self, line: &str, words: &[LineWord]) -> String {
+        if line.contains('"') {
+            operand_text(line, words).to_string()
+        } else {
+            words.iter().map(|(word, _)| *word).collect::<Vec<_>>().join(" ")
+        }
+    }
+
+    // mnemonic padded to the operand column; macro calls and unknown words
+    // are left as they are
+    fn instruction(&self, dialect: Dialect, line: &str, words: &[LineWord]) -> String {
+        let mnemonic = words[0].0.to_lowercase();
+        let defs: Vec<_> = self.isa.instructions().iter().filter(|def| def.mnemonic == mnemonic).collect();
+        if defs.is_empty() {
+            return words.iter().map(|(word, _)| *word).collect::<Vec<_>>().join(" ");
+        }
+
+        // book operands are separated by commas and may have spaces inside
+        let mut operands: Vec<String> = match dialect {
+            Dialect::Book => operand_text(line, &words[1..])
+                .split(',')
+                .map(|operand| operand.split_whitespace().collect::<Vec<_>>().join(" "))
+                .filter(|operand| !operand.is_empty())
+                .map(|operand| match operand.to_lowercase().as_str() {
+                    "a" | "b" | "[b]" => operand.to_uppercase(),
+                    _ => operand,
+                })
+                .collect(),
+            Dialect::Native => words[1..].iter().map(|(word, _)| word.to_string()).collect(),
+        };
+
+        let address = defs.iter().any(|def| def.value_operand() == Some(Operand::Address));
+        for operand in operands.iter_mut() {
+            let width = if address || operand.starts_with('[') { 8 } else { 4 };
+            *operand = literals(operand, width);
+        }
+
+        let (mnemonic, separator) = match dialect {
+            Dialect::Native => (mnemonic, " "),
+            Dialect::Book => (mnemonic.to_uppercase(), ", "),
+        };
+        if operands.is_empty() {
+            mnemonic
+        } else {
+            format!("{:<width$}{}", mnemonic, operands.join(separator), width = MNEMONIC_WIDTH)
+        }
+    }
+}
+
+// source text from the first word to the end of the last
+fn operand_text<'a>(line: &'a str, words: &[LineWord]) -> &'a str {
+    match (words.first(), words.last()) {
+        (Some((_, start)), Some((word, end))) => &line[start - 1..end - 1 + word.len()],
+        _ => "",
+    }
+}
+
+// every number literal in text in binary with at least width digits;
+// names, operators and quoted text stay as they are
+fn literals(text: &str, width: usize) -> String {
+    let identifier = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut result = String::new();
+    let mut rest = text;
+    let mut quoted = false;
+
+    while let Some(c) = rest.chars().next() {
+        let len = match identifier(c) && !quoted {
+            true => rest.find(|c: char| !identifier(c)).unwrap_or(rest.len()),
+            false => c.len_utf8(),
+        };
+        let word = &rest[..len];
+        quoted ^= c == '"';
+        match expression::evaluate(word, &|_| None) {
+            Ok(value) if c.is_ascii_digit() && !quoted => {
+                result.push_str(&format!("{:0width$b}", value, width = width));
+            },
+            _ => result.push_str(word),
+        }
+        rest = &rest[len..];
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod formatter_tests {
+    use crate::emulator::formatter::Formatter;
+    use crate::emulator::parser::{Dialect, Parser};
+
+    fn format_as(dialect: Dialect, source: &str) -> String {
+        let mut parser = Parser::new(source.lines().map(String::from).collect());
+        parser.set_dialect(dialect);
+        Formatter::new().format(&mut parser, source).unwrap()
+    }
+
+    fn format(source: &str) -> String {
+        format_as(Dialect::Native, source)
+    }
+
+    const SOURCE: &str = "\
+; counts up on the port\n\
+.equ   STEP   1\n\
+LIMIT = 0xF\n\
+.macro wait n\n\
+  add B n\n\
+.endm\n\
+\n\
+\n\
+start:\tmov A 0\n\
+loop: add A STEP ; next\n\
+  out   B\n\
+  jnc loop\n\
+  ld A [0x10]\n\
+  call 0b11\n\
+wait 1   ;   macro call\n\
+  .byte 1, 0x2\n\
+.assert end < 0x10, \"too 1 long\"\n\
+end:  hlt\n\
+\n";
+
+    const FORMATTED: &str = "\
+; counts up on the port
+.equ STEP 0001
+LIMIT = 1111
+.macro wait n
+        add  B n
+.endm
+
+start:  mov  A 0000
+loop:   add  A STEP             ; next
+        out  B
+        jnc  loop
+        ld   A [00010000]
+        call 00000011
+        wait 1                  ; macro call
+        .byte 00000001, 00000010
+.assert end < 10000, \"too 1 long\"
+end:    hlt
+";
+
+    #[test]
+    fn format_test() {
+        assert_eq!(format(SOURCE), FORMATTED);
+        assert_eq!(format(FORMATTED), FORMATTED);
+        assert_eq!(format("lamp: .byte 1\nlong_label: out 0b1\n"), "lamp:   .byte 00000001\nlong_label: out  0001\n");
+        assert_eq!(format("  ; inside\n;; heading\n"), "        ; inside\n;; heading\n");
+        assert_eq!(
+            format("N = 1\nout 10 + 1\nld A [0x10+N]\n.org 0x20\n.fill 0x2, 0b1\nX_1 = -(0x3 << 1)\n.if X_1 == 1\n.endif\n"),
+            "N = 0001\n        out  0010 + 0001\n        ld   A [00010000+N]\n.org 00100000\n        .fill 00000010, 00000001\nX_1 = -(0011 << 0001)\n.if X_1 == 0001\n.endif\n"
+        );
+    }
+
+    #[test]
+    fn format_book_test() {
+        assert_eq!(format_as(Dialect::Book, "top: mov a,b\n  Jnc top\nout 0x3\n"), "top:    MOV  A, B\n        JNC  top\n        OUT  0011\n");
+        assert_eq!(format_as(Dialect::Book, "N = 10\nADD A,N  -  1\nadd\tb ,1\n"), "N = 0010\n        ADD  A, N - 0001\n        ADD  B, 0001\n");
+    }
+
+    // source that does not parse is refused instead of rewritten
+    #[test]
+    fn format_error_test() {
+        let mut parser = Parser::new(vec!["MOV A 1".to_string()]);
+        assert!(Formatter::new().format(&mut parser, "MOV A 1\n").is_err());
+        let mut parser = Parser::new(vec!["add A".to_string()]);
+        assert!(Formatter::new().format(&mut parser, "add A\n").is_err());
+    }
+
+    // formatting does not change what the source assembles to
+    #[test]
+    fn format_keeps_tokens_test() {
+        let source: Vec<String> = "top:\tmov A 0\nadd A 0b1 ; count\nout B\njnc top\nhlt".lines().map(String::from).collect();
+        let formatted: Vec<String> = format(&source.join("\n")).lines().map(String::from).collect();
+
+        let tokens = |lines: Vec<String>| format!("{:?}", Parser::new(lines).parse().unwrap());
+        assert_eq!(tokens(source), tokens(formatted));
+    }
+}
@@ -1,35 +1,81 @@
 
 use crate::emulator::register::RegisterOp;
 
-use num_derive::FromPrimitive;
-
-
-#[derive(Debug, PartialEq, FromPrimitive)]
-pub enum Opcodes {
-    AddA = 0b0000,
-    AddB = 0b0101,
-    MovA = 0b0011,
-    MovB = 0b0111,
-    MovA2B = 0b0001,
-    MovB2A = 0b0100,
-    Jmp = 0b1111,
-    Jnc = 0b1110,
-    InA = 0b0010,
-    InB = 0b0110,
-    OutB = 0b1001,
-    OutIm = 0b1011,
+
+// register to register operations; the destination is the register of the token
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AluOp {
+    Add,
+    And,
+    Or,
+    Xor,
+    Not,
+    Shl,
+    Shr,
+    Rcl,
+    Rcr,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Address {
+    Direct(u8),
+    IndexB,
 }
 
 #[derive(Debug)]
 pub enum Token {
     Add(RegisterOp, u8),
+    Sub(RegisterOp, u8),
+    Cmp(RegisterOp, u8),
     Mov(RegisterOp, u8),
     MovAB,
     MovBA,
     Jmp(u8),
     Jnc(u8),
+    Jz(u8),
+    Jnz(u8),
     In(RegisterOp),
     OutB,
     OutIm(u8),
+    Ld(Address),
+    St(Address),
+    Bank(u8),
+    Alu(AluOp, RegisterOp),
+    Call(u8),
+    Ret,
+    Hlt,
+    Nop,
+    Reti,
+    Ei,
+    Di,
+    // assembler placement, emits no instruction
+    Org(usize),
+    // raw rom word
+    Byte(u8),
+    // words .fill leaves free, emits nothing
+    Reserve(usize),
+}
+
+impl Token {
+    // number of rom words the token occupies
+    pub fn size(&self) -> usize {
+        match self {
+            Token::Ld(Address::Direct(_))
+            | Token::St(Address::Direct(_))
+            | Token::Bank(_)
+            | Token::Sub(RegisterOp::B, _)
+            | Token::Cmp(_, _)
+            | Token::Alu(_, _)
+            | Token::Call(_) => 2,
+            Token::Org(_) => 0,
+            Token::Reserve(count) => *count,
+            _ => 1,
+        }
+    }
+
+    // an instruction the cpu executes, not placement or data
+    pub fn is_instruction(&self) -> bool {
+        self.size() > 0 && !matches!(self, Token::Byte(_) | Token::Reserve(_))
+    }
 }
 
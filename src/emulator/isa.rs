@@ -0,0 +1,181 @@
+use std::fmt;
+use crate::emulator::executor::CPUemulator;
+use crate::emulator::instructions::Token;
+use crate::emulator::register::Register;
+use crate::EmulatorErr;
+
+// one entry of the register file as traces and debuggers show it
+#[derive(Debug, Clone, Copy)]
+pub struct RegisterLayout {
+    pub name: &'static str,
+    pub width: u8,
+    pub read: fn(&Register) -> u16,
+}
+
+// what the assembler accepts in an operand position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    // written exactly like this: A, B, [B]
+    Keyword(&'static str),
+    // 4 bit binary
+    Immediate,
+    // 4 bit binary or a label in reach of the bank register
+    Jump,
+    // 8 bit binary or a label
+    Address,
+    // [8 bit binary]
+    Memory,
+}
+
+impl Operand {
+    pub fn keyword(&self) -> bool {
+        matches!(self, Operand::Keyword(_))
+    }
+
+    pub fn format(&self, value: u8) -> String {
+        match self {
+            Operand::Keyword(text) => text.to_string(),
+            Operand::Immediate | Operand::Jump => format!("{:04b}", value),
+            Operand::Address => format!("{:08b}", value),
+            Operand::Memory => format!("[{:08b}]", value),
+        }
+    }
+}
+
+// where an instruction and its operand value sit in rom
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    // opcode nibble, the value in the low nibble
+    Opcode(u8),
+    // prefix nibble and sub opcode, the value in a second word when there is one
+    Prefix(u8, u8),
+    // prefix nibble, sub opcode and a fixed second word
+    PrefixWord(u8, u8, u8),
+}
+
+impl Encoding {
+    pub fn encode(&self, value: Option<u8>) -> Vec<u8> {
+        match *self {
+            Encoding::Opcode(op) => vec![(op << 4) | (value.unwrap_or(0) & 0x0f)],
+            Encoding::Prefix(prefix, sub) => {
+                let mut words = vec![(prefix << 4) | sub];
+                words.extend(value);
+                words
+            },
+            Encoding::PrefixWord(prefix, sub, word) => vec![(prefix << 4) | sub, word],
+        }
+    }
+
+    // operand value and size when the words at address hold this encoding
+    pub fn decode(&self, words: &[u8], address: usize, value: bool) -> Result<Option<(u8, usize)>, EmulatorErr> {
+        let word = words[address];
+        let next = words.get(address + 1).copied();
+
+        match *self {
+            Encoding::Opcode(op) if word >> 4 == op => {
+                Ok(Some((if value { word & 0x0f } else { 0 }, 1)))
+            },
+            Encoding::Prefix(prefix, sub) if word == (prefix << 4) | sub => {
+                if !value {
+                    return Ok(Some((0, 1)));
+                }
+                let operand = next.ok_or_else(|| EmulatorErr::new("Missing operand word"))?;
+                Ok(Some((operand, 2)))
+            },
+            Encoding::PrefixWord(prefix, sub, fixed) if word == (prefix << 4) | sub => {
+                match next {
+                    Some(operand) if operand == fixed => Ok(Some((0, 2))),
+                    Some(_) => Ok(None),
+                    None => Err(EmulatorErr::new("Missing operand word")),
+                }
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+// one row of an instruction table: everything the assembler, executor and
+// disassembler need to know about an instruction. An instruction has at most
+// one operand that is not a keyword, its value is what the hooks pass around.
+pub struct InstructionDef<I: Isa> {
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+    // what the instruction does, for hover and completion in editors
+    pub description: &'static str,
+    pub encoding: Encoding,
+    // token for an operand value
+    pub token: fn(u8) -> Token,
+    // operand value of a token of this instruction, None for any other token
+    pub value: fn(&Token) -> Option<u8>,
+    pub execute: fn(&mut CPUemulator<I>, u8) -> Result<(), EmulatorErr>,
+}
+
+impl<I: Isa> InstructionDef<I> {
+    // the operand that carries a value, if any
+    pub fn value_operand(&self) -> Option<Operand> {
+        self.operands.iter().copied().find(|o| !o.keyword())
+    }
+
+    // mnemonic and operand kinds, like ld A [addr]
+    pub fn syntax(&self) -> String {
+        let mut words = vec![self.mnemonic.to_string()];
+        words.extend(self.operands.iter().map(|operand| match operand {
+            Operand::Keyword(text) => text.to_string(),
+            Operand::Immediate | Operand::Jump => "imm".to_string(),
+            Operand::Address => "addr".to_string(),
+            Operand::Memory => "[addr]".to_string(),
+        }));
+        words.join(" ")
+    }
+}
+
+// a cpu design: its instruction table and register file. Token is the
+// instruction form shared with the parser; decode, execute, encode and
+// disassemble all follow from the table.
+pub trait Isa: Clone + fmt::Debug + Sized + 'static {
+    fn name(&self) -> &'static str;
+
+    fn registers(&self) -> &'static [RegisterLayout];
+
+    fn instructions(&self) -> &'static [InstructionDef<Self>];
+
+    // table entry of a token and its operand value
+    fn lookup(&self, token: &Token) -> Result<(&'static InstructionDef<Self>, u8), EmulatorErr> {
+        self.instructions().iter()
+            .find_map(|def| (def.value)(token).map(|value| (def, value)))
+            .ok_or_else(|| EmulatorErr::new(&format!("{:?} is not a {} instruction", token, self.name())))
+    }
+
+    // instruction at address and the number of rom words it uses
+    fn decode(&self, words: &[u8], address: usize) -> Result<(Token, usize), EmulatorErr> {
+        if address >= words.len() {
+            return Err(EmulatorErr::new("Address past the end of rom"));
+        }
+
+        for def in self.instructions() {
+            if let Some((value, size)) = def.encoding.decode(words, address, def.value_operand().is_some())? {
+                return Ok(((def.token)(value), size));
+            }
+        }
+        Err(EmulatorErr::new("No match instruction"))
+    }
+
+    // pc already points at the next instruction when this runs
+    fn execute(&self, cpu: &mut CPUemulator<Self>, instruction: Token) -> Result<(), EmulatorErr> {
+        let (def, value) = self.lookup(&instruction)?;
+        (def.execute)(cpu, value)
+    }
+
+    fn encode(&self, token: &Token) -> Result<Vec<u8>, EmulatorErr> {
+        let (def, value) = self.lookup(token)?;
+        Ok(def.encoding.encode(def.value_operand().map(|_| value)))
+    }
+
+    // in the syntax Parser accepts
+    fn disassemble(&self, instruction: &Token) -> Result<String, EmulatorErr> {
+        let (def, value) = self.lookup(instruction)?;
+        let mut words = vec![def.mnemonic.to_string()];
+        words.extend(def.operands.iter().map(|o| o.format(value)));
+        Ok(words.join(" "))
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+use crate::EmulatorErr;
+
+// the JSON the language server reads and writes; object keys keep their order
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    pub fn string(text: &str) -> Json {
+        Json::String(text.to_string())
+    }
+
+    pub fn number(value: usize) -> Json {
+        Json::Number(value as f64)
+    }
+
+    // member of an object, Null for anything missing
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => fields.iter()
+                .find(|(name, _)| name == key)
+                .map_or(&Json::Null, |(_, value)| value),
+            _ => &Json::Null,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(value) if *value >= 0.0 && value.fract() == 0.0 => Some(*value as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(value) if value.fract() == 0.0 && value.abs() < 1e15 => Some(*value as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, EmulatorErr> {
+        let mut reader = Reader { text, position: 0 };
+        let value = reader.value()?;
+        reader.whitespace();
+        if reader.position < text.len() {
+            return Err(reader.error("end of json"));
+        }
+        Ok(value)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) if value.fract() == 0.0 && value.abs() < 1e15 => write!(f, "{}", *value as i64),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(text) => write_string(f, text),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn error(&self, expected: &str) -> EmulatorErr {
+        EmulatorErr::new(&format!("json: expected {} at byte {}", expected, self.position))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, EmulatorErr> {
+        if !self.text[self.position..].starts_with(word) {
+            return Err(self.error(word));
+        }
+        self.position += word.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, EmulatorErr> {
+        self.whitespace();
+        match self.peek() {
+            Some('n') => self.literal("null", Json::Null),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') => {
+                self.position += 1;
+                let mut items = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(']') {
+                    self.position += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(',') => self.position += 1,
+                        Some(']') => {
+                            self.position += 1;
+                            return Ok(Json::Array(items));
+                        },
+                        _ => return Err(self.error(", or ]")),
+                    }
+                }
+            },
+            Some('{') => {
+                self.position += 1;
+                let mut fields = Vec::new();
+                self.whitespace();
+                if self.peek() == Some('}') {
+                    self.position += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.whitespace();
+                    if self.peek() != Some('"') {
+                        return Err(self.error("a key"));
+                    }
+                    let key = self.string()?;
+                    self.whitespace();
+                    if self.peek() != Some(':') {
+                        return Err(self.error(":"));
+                    }
+                    self.position += 1;
+                    fields.push((key, self.value()?));
+                    self.whitespace();
+                    match self.peek() {
+                        Some(',') => self.position += 1,
+                        Some('}') => {
+                            self.position += 1;
+                            return Ok(Json::Object(fields));
+                        },
+                        _ => return Err(self.error(", or }")),
+                    }
+                }
+            },
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let len = self.text[self.position..]
+                    .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+                    .unwrap_or(self.text.len() - self.position);
+                let number = &self.text[self.position..self.position + len];
+                let value = number.parse().map_err(|_| self.error("a number"))?;
+                self.position += len;
+                Ok(Json::Number(value))
+            },
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    // position is on the opening quote
+    fn string(&mut self) -> Result<String, EmulatorErr> {
+        self.position += 1;
+        let mut text = String::new();
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("\""))?;
+            self.position += c.len_utf8();
+            match c {
+                '"' => return Ok(text),
+                '\\' => {
+                    let escape = self.peek().ok_or_else(|| self.error("an escape"))?;
+                    match escape {
+                        '"' | '\\' | '/' => text.push(escape),
+                        'n' => text.push('\n'),
+                        'r' => text.push('\r'),
+                        't' => text.push('\t'),
+                        'b' => text.push('\u{8}'),
+                        'f' => text.push('\u{c}'),
+                        'u' => {},
+                        _ => return Err(self.error("an escape")),
+                    }
+                    self.position += 1;
+                    if escape == 'u' {
+                        let code = self.hex()?;
+                        text.push(self.surrogate_pair(code)?.unwrap_or_else(|| char::from_u32(code).unwrap_or('\u{fffd}')));
+                    }
+                },
+                c => text.push(c),
+            }
+        }
+    }
+
+    // a surrogate pair is one character in two escapes; a high half without
+    // its low half is U+FFFD and whatever follows is read on its own
+    fn surrogate_pair(&mut self, high: u32) -> Result<Option<char>, EmulatorErr> {
+        if !(0xd800..0xdc00).contains(&high) || !self.text[self.position..].starts_with("\\u") {
+            return Ok(None);
+        }
+        let position = self.position;
+        self.position += 2;
+        let low = self.hex()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            self.position = position;
+            return Ok(Some('\u{fffd}'));
+        }
+        Ok(char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)))
+    }
+
+    fn hex(&mut self) -> Result<u32, EmulatorErr> {
+        let digits = self.text.get(self.position..self.position + 4)
+            .filter(|digits| digits.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("4 hex digits"))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("4 hex digits"))?;
+        self.position += 4;
+        Ok(code)
+    }
+}
+
+
+#[cfg(test)]
+mod json_tests {
+    use crate::emulator::json::Json;
+
+    #[test]
+    fn json_round_trip_test() {
+        let text = r#"{"id":1,"params":{"text":"mov A 1\n\"q\"","list":[true,false,null,-2.5]}}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(json.get("id").as_usize(), Some(1));
+        assert_eq!(json.get("params").get("text").as_str(), Some("mov A 1\n\"q\""));
+        assert_eq!(json.get("params").get("list").as_array().unwrap()[3], Json::Number(-2.5));
+        assert_eq!(json.get("missing"), &Json::Null);
+        assert_eq!(json.to_string(), text);
+
+        let json = Json::parse(" { \"a\" : [ ] , \"b\" : \"\\u00e9\\ud83d\\ude00\" } ").unwrap();
+        assert_eq!(json.get("b").as_str(), Some("é😀"));
+    }
+
+    #[test]
+    fn json_error_test() {
+        assert_eq!(Json::parse("{\"a\" 1}").unwrap_err().msg(), "json: expected : at byte 5");
+        assert_eq!(Json::parse("[1,").unwrap_err().msg(), "json: expected a value at byte 3");
+        assert_eq!(Json::parse("1 2").unwrap_err().msg(), "json: expected end of json at byte 2");
+        assert_eq!(Json::parse("\"\\é\"").unwrap_err().msg(), "json: expected an escape at byte 2");
+        assert_eq!(Json::parse("\"\\u+041\"").unwrap_err().msg(), "json: expected 4 hex digits at byte 3");
+        assert_eq!(Json::parse(r#""\ud800\u0041""#).unwrap(), Json::string("\u{fffd}A"));
+        assert_eq!(Json::parse(r#""\udc00 \ud800""#).unwrap(), Json::string("\u{fffd} \u{fffd}"));
+    }
+}
@@ -0,0 +1,727 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+use crate::emulator::instructions::Token;
+use crate::emulator::isa::Isa;
+use crate::emulator::json::Json;
+use crate::emulator::lint::Linter;
+use crate::emulator::machine::MachineConfig;
+use crate::emulator::parser::{line_words, Dialect, Parser, SymbolKind};
+use crate::emulator::source_map::SourceLocation;
+use crate::emulator::td4::Td4;
+use crate::EmulatorErr;
+
+const REGISTERS: [&str; 3] = ["A", "B", "[B]"];
+
+// lsp diagnostic severities and symbol kinds
+const ERROR: usize = 1;
+const WARNING: usize = 2;
+const FUNCTION_SYMBOL: usize = 12;
+const CONSTANT_SYMBOL: usize = 14;
+const FUNCTION_ITEM: usize = 3;
+const VARIABLE_ITEM: usize = 6;
+const KEYWORD_ITEM: usize = 14;
+const CONSTANT_ITEM: usize = 21;
+
+// json-rpc error codes
+const METHOD_NOT_FOUND: f64 = -32601.0;
+const INVALID_REQUEST: f64 = -32600.0;
+const PARSE_ERROR: f64 = -32700.0;
+
+// largest message body read; sources of a few rom banks are far smaller
+const MAX_MESSAGE: usize = 1 << 20;
+
+// a message from the client, or why it could not be read
+pub type Message = Result<Json, EmulatorErr>;
+
+// a language server for .sasm over json-rpc: diagnostics from the parser,
+// layout checks and lint, hover with the encoding and what an instruction
+// does, go to definition, completion and document symbols
+#[derive(Debug)]
+pub struct LanguageServer<I: Isa = Td4> {
+    isa: I,
+    dialect: Dialect,
+    // rom words, include paths and -D defines the documents assemble with
+    rom_size: usize,
+    address_width: u8,
+    include_paths: Vec<String>,
+    defines: Vec<(String, i64)>,
+    // uri -> text of the open documents
+    documents: HashMap<String, String>,
+    shutdown: bool,
+    exited: bool,
+}
+
+// a parse of one document
+struct Analysis<I: Isa> {
+    path: String,
+    parser: Parser<I>,
+    tokens: Vec<Token>,
+    error: Option<EmulatorErr>,
+}
+
+impl LanguageServer {
+    pub fn new() -> LanguageServer {
+        LanguageServer::with_isa(Td4::new())
+    }
+}
+
+impl Default for LanguageServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I: Isa> LanguageServer<I> {
+    pub fn with_isa(isa: I) -> Self {
+        LanguageServer {
+            isa,
+            dialect: Dialect::Native,
+            rom_size: MachineConfig::td4().rom_size(),
+            address_width: MachineConfig::td4().address_width(),
+            include_paths: Vec::new(),
+            defines: Vec::new(),
+            documents: HashMap::new(),
+            shutdown: false,
+            exited: false,
+        }
+    }
+
+    // serve messages from reader until exit or the end of input; a message
+    // that is not json gets a parse error and the server goes on
+    pub fn run(&mut self, reader: &mut impl BufRead, writer: &mut impl Write) -> Result<(), EmulatorErr> {
+        while let Some(message) = read_message(reader)? {
+            let replies = match message {
+                Ok(message) => self.handle(&message),
+                Err(e) => vec![error_response(&Json::Null, PARSE_ERROR, e.msg())],
+            };
+            for reply in replies {
+                write_message(writer, &reply)?;
+            }
+            if self.exited {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // responses and notifications for one message from the client
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let id = message.get("id");
+        let params = message.get("params");
+        let method = message.get("method").as_str().unwrap_or("");
+        if self.shutdown && method != "exit" && *id != Json::Null {
+            return vec![error_response(id, INVALID_REQUEST, "server is shut down")];
+        }
+
+        match method {
+            "initialize" => {
+                self.configure(params.get("initializationOptions"));
+                vec![response(id, capabilities())]
+            },
+            "shutdown" => {
+                self.shutdown = true;
+                vec![response(id, Json::Null)]
+            },
+            "exit" => {
+                self.exited = true;
+                Vec::new()
+            },
+            "textDocument/didOpen" => {
+                let document = params.get("textDocument");
+                let uri = document.get("uri").as_str().unwrap_or("");
+                self.documents.insert(uri.to_string(), document.get("text").as_str().unwrap_or("").to_string());
+                vec![self.diagnostics(uri)]
+            },
+            "textDocument/didChange" => {
+                let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+                // full sync: the last change holds the whole text
+                let text = params.get("contentChanges").as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text").as_str());
+                if let Some(text) = text {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+                vec![self.diagnostics(uri)]
+            },
+            "textDocument/didClose" => {
+                let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+                self.documents.remove(uri);
+                vec![publish(uri, Vec::new())]
+            },
+            "textDocument/hover" => vec![response(id, self.hover(params))],
+            "textDocument/definition" => vec![response(id, self.definition(params))],
+            "textDocument/completion" => vec![response(id, self.completion(params))],
+            "textDocument/documentSymbol" => vec![response(id, self.document_symbols(params))],
+            _ if *id != Json::Null => vec![error_response(id, METHOD_NOT_FOUND, &format!("unknown method {}", method))],
+            // notifications the server does not use
+            _ => Vec::new(),
+        }
+    }
+
+    // initializationOptions like the command line options:
+    // {"dialect": "book", "romSize": 256, "addressWidth": 8, "includePaths": ["lib"], "defines": {"DEBUG": 1}}
+    fn configure(&mut self, options: &Json) {
+        if let Some(dialect) = options.get("dialect").as_str() {
+            self.dialect = dialect.parse().unwrap_or_default();
+        }
+        if let Some(rom_size) = options.get("romSize").as_usize() {
+            self.rom_size = rom_size;
+        }
+        if let Some(address_width) = options.get("addressWidth").as_usize() {
+            self.address_width = address_width.min(u8::MAX as usize) as u8;
+        }
+        if let Some(paths) = options.get("includePaths").as_array() {
+            self.include_paths = paths.iter().filter_map(Json::as_str).map(String::from).collect();
+        }
+        if let Json::Object(defines) = options.get("defines") {
+            self.defines = defines.iter()
+                .filter_map(|(name, value)| Some((name.clone(), value.as_i64()?)))
+                .collect();
+        }
+    }
+
+    fn analyze(&self, uri: &str) -> Option<Analysis<I>> {
+        let text = self.documents.get(uri)?;
+        let path = uri_path(uri);
+        let mut parser = Parser::with_isa(self.isa.clone(), &path, text.lines().map(String::from).collect());
+        parser.set_dialect(self.dialect);
+        for path in &self.include_paths {
+            parser.add_include_path(path);
+        }
+        for (name, value) in &self.defines {
+            parser.define(name, *value);
+        }
+        let (tokens, error) = match parser.parse() {
+            Ok(tokens) => match parser.check_layout(&tokens, self.rom_size, self.address_width) {
+                Ok(()) => (tokens, None),
+                Err(e) => (tokens, Some(e)),
+            },
+            Err(e) => (Vec::new(), Some(e)),
+        };
+        Some(Analysis { path, parser, tokens, error })
+    }
+
+    fn diagnostics(&self, uri: &str) -> Json {
+        let Some(analysis) = self.analyze(uri) else {
+            return publish(uri, Vec::new());
+        };
+        let text = &self.documents[uri];
+        let mut diagnostics = Vec::new();
+        let mut add = |location: Option<&SourceLocation>, message: &str, severity: usize, code: Option<&str>| {
+            // messages from other files, like include errors, go on the first line
+            let range = match location {
+                Some(location) if location.file == analysis.path => word_range(text, location),
+                _ => range(0, 0, 0),
+            };
+            let mut fields = vec![
+                ("range", range),
+                ("severity", Json::number(severity)),
+                ("source", Json::string("sasm")),
+                ("message", Json::string(message)),
+            ];
+            fields.extend(code.map(|code| ("code", Json::string(code))));
+            diagnostics.push(Json::object(fields));
+        };
+
+        if let Some(error) = &analysis.error {
+            add(error.location(), error.text(), ERROR, None);
+        }
+        for warning in analysis.parser.warnings() {
+            add(warning.location.as_ref(), &warning.message, WARNING, None);
+        }
+        if analysis.error.is_none() {
+            for lint in Linter::with_isa(self.isa.clone()).lint(&analysis.parser, &analysis.tokens) {
+                add(Some(&lint.location), &lint.message, WARNING, Some(lint.id));
+            }
+        }
+        publish(uri, diagnostics)
+    }
+
+    // what the word under the cursor means and what its line assembles to
+    fn hover(&self, params: &Json) -> Json {
+        let Some((uri, line, character)) = position(params) else { return Json::Null };
+        let Some(analysis) = self.analyze(uri) else { return Json::Null };
+        let Some((word, _)) = word_at(&self.documents[uri], line, character) else { return Json::Null };
+
+        let mut parts = Vec::new();
+        let name = symbol_name(word);
+        if let Some(symbol) = analysis.parser.symbols().iter().find(|s| s.name == name) {
+            parts.push(format!("`{}`", symbol));
+        }
+        if let Some(semantics) = self.semantics(word) {
+            parts.push(semantics);
+        }
+        let encodings: Vec<String> = analysis.tokens.iter().zip(analysis.parser.locations())
+            .filter(|(_, location)| location.file == analysis.path && location.line == line + 1)
+            .filter_map(|(token, _)| {
+                let text = self.isa.disassemble(token).ok()?;
+                let words = self.isa.encode(token).ok()?;
+                let words: Vec<String> = words.iter().map(|w| format!("{:08b}", w)).collect();
+                Some(format!("`{}` encodes as `{}`", text, words.join(" ")))
+            })
+            .collect();
+        parts.extend(encodings);
+
+        if parts.is_empty() {
+            return Json::Null;
+        }
+        Json::object(vec![("contents", Json::object(vec![
+            ("kind", Json::string("markdown")),
+            ("value", Json::string(&parts.join("\n\n"))),
+        ]))])
+    }
+
+    // where the label or constant under the cursor is defined
+    fn definition(&self, params: &Json) -> Json {
+        let Some((uri, line, character)) = position(params) else { return Json::Null };
+        let Some(analysis) = self.analyze(uri) else { return Json::Null };
+        let Some((word, _)) = word_at(&self.documents[uri], line, character) else { return Json::Null };
+
+        let name = symbol_name(word);
+        match analysis.parser.symbols().iter().find(|s| s.name == name) {
+            Some(symbol) => Json::object(vec![
+                ("uri", Json::string(&path_uri(&symbol.location.file))),
+                ("range", span(&self.source_line(&symbol.location), &symbol.location, name.len())),
+            ]),
+            None => Json::Null,
+        }
+    }
+
+    // what every form of a mnemonic does, from the instruction table
+    fn semantics(&self, mnemonic: &str) -> Option<String> {
+        let forms: Vec<String> = self.isa.instructions().iter()
+            .filter(|def| def.mnemonic.eq_ignore_ascii_case(mnemonic))
+            .map(|def| format!("{}: {}", def.syntax(), def.description))
+            .collect();
+        (!forms.is_empty()).then(|| forms.join("\n"))
+    }
+
+    // text of a source line, from the open document or else from disk
+    fn source_line(&self, at: &SourceLocation) -> String {
+        let text = self.documents.get(&path_uri(&at.file)).cloned()
+            .or_else(|| fs::read_to_string(&at.file).ok())
+            .unwrap_or_default();
+        text.lines().nth(at.line - 1).unwrap_or("").to_string()
+    }
+
+    fn completion(&self, params: &Json) -> Json {
+        let mut items = Vec::new();
+        let mut mnemonics: Vec<&str> = Vec::new();
+        for def in self.isa.instructions() {
+            if !mnemonics.contains(&def.mnemonic) {
+                mnemonics.push(def.mnemonic);
+            }
+        }
+        for mnemonic in mnemonics {
+            let label = match self.dialect {
+                Dialect::Native => mnemonic.to_string(),
+                Dialect::Book => mnemonic.to_uppercase(),
+            };
+            items.push(completion_item(&label, KEYWORD_ITEM, &self.semantics(mnemonic).unwrap_or_default()));
+        }
+        for register in REGISTERS {
+            items.push(completion_item(register, VARIABLE_ITEM, "register"));
+        }
+
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+        if let Some(analysis) = self.analyze(uri) {
+            for symbol in analysis.parser.symbols() {
+                let kind = match symbol.kind {
+                    SymbolKind::Label => FUNCTION_ITEM,
+                    SymbolKind::Constant => CONSTANT_ITEM,
+                };
+                items.push(completion_item(&symbol.name, kind, &symbol.to_string()));
+            }
+        }
+        Json::Array(items)
+    }
+
+    // labels and constants of the document, not the ones of its include files
+    fn document_symbols(&self, params: &Json) -> Json {
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+        let Some(analysis) = self.analyze(uri) else { return Json::Array(Vec::new()) };
+
+        Json::Array(analysis.parser.symbols().iter()
+            .filter(|symbol| symbol.location.file == analysis.path)
+            .map(|symbol| {
+                let kind = match symbol.kind {
+                    SymbolKind::Label => FUNCTION_SYMBOL,
+                    SymbolKind::Constant => CONSTANT_SYMBOL,
+                };
+                let range = word_range(&self.documents[uri], &symbol.location);
+                Json::object(vec![
+                    ("name", Json::string(&symbol.name)),
+                    ("detail", Json::string(&format!("{} {}", symbol.kind, symbol.value_text()))),
+                    ("kind", Json::number(kind)),
+                    ("range", range.clone()),
+                    ("selectionRange", range),
+                ])
+            })
+            .collect())
+    }
+}
+
+// one json-rpc message after its Content-Length header, None at the end of
+// input; Err only when reading fails, a body that is not json or is longer
+// than MAX_MESSAGE is skipped and an Err message
+pub fn read_message(reader: &mut impl BufRead) -> Result<Option<Message>, EmulatorErr> {
+    let mut length = None;
+    loop {
+        let mut header = Vec::new();
+        let read = reader.read_until(b'\n', &mut header).map_err(|e| EmulatorErr::new(&format!("read message: {}", e)))?;
+        if read == 0 {
+            return Ok(None);
+        }
+        let header = String::from_utf8_lossy(&header);
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let Some(length) = length else {
+        return Ok(Some(Err(EmulatorErr::new("message without Content-Length"))));
+    };
+    if length > MAX_MESSAGE {
+        let skipped = io::copy(&mut reader.take(length as u64), &mut io::sink())
+            .map_err(|e| EmulatorErr::new(&format!("read message: {}", e)))?;
+        if skipped < length as u64 {
+            return Ok(None);
+        }
+        return Ok(Some(Err(EmulatorErr::new(&format!(
+            "message of {} bytes is longer than {} bytes", length, MAX_MESSAGE
+        )))));
+    }
+    let mut body = vec![0; length];
+    match reader.read_exact(&mut body) {
+        Ok(()) => {},
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(EmulatorErr::new(&format!("read message: {}", e))),
+    }
+    Ok(Some(String::from_utf8(body)
+        .map_err(|_| EmulatorErr::new("message is not utf-8"))
+        .and_then(|body| Json::parse(&body))))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Json) -> Result<(), EmulatorErr> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| writer.flush())
+        .map_err(|e| EmulatorErr::new(&format!("write message: {}", e)))
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        ("capabilities", Json::object(vec![
+            // full text on every change
+            ("textDocumentSync", Json::number(1)),
+            ("hoverProvider", Json::Bool(true)),
+            ("definitionProvider", Json::Bool(true)),
+            ("completionProvider", Json::object(vec![])),
+            ("documentSymbolProvider", Json::Bool(true)),
+        ])),
+        ("serverInfo", Json::object(vec![("name", Json::string("sasm-lsp"))])),
+    ])
+}
+
+fn response(id: &Json, result: Json) -> Json {
+    Json::object(vec![("jsonrpc", Json::string("2.0")), ("id", id.clone()), ("result", result)])
+}
+
+fn error_response(id: &Json, code: f64, message: &str) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::string("2.0")),
+        ("id", id.clone()),
+        ("error", Json::object(vec![("code", Json::Number(code)), ("message", Json::string(message))])),
+    ])
+}
+
+fn publish(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::string("2.0")),
+        ("method", Json::string("textDocument/publishDiagnostics")),
+        ("params", Json::object(vec![("uri", Json::string(uri)), ("diagnostics", Json::Array(diagnostics))])),
+    ])
+}
+
+fn completion_item(label: &str, kind: usize, detail: &str) -> Json {
+    Json::object(vec![
+        ("label", Json::string(label)),
+        ("kind", Json::number(kind)),
+        ("detail", Json::string(detail)),
+    ])
+}
+
+fn range(line: usize, start: usize, end: usize) -> Json {
+    let position = |character| Json::object(vec![("line", Json::number(line)), ("character", Json::number(character))]);
+    Json::object(vec![("start", position(start)), ("end", position(end))])
+}
+
+// len bytes from a parser location as an lsp range; lsp counts characters
+// in utf-16 code units, parser columns are bytes
+fn span(line: &str, at: &SourceLocation, len: usize) -> Json {
+    let start = at.column - 1;
+    range(at.line - 1, utf16_column(line, start), utf16_column(line, start + len))
+}
+
+// the word starting at a parser location, as an lsp range
+fn word_range(text: &str, at: &SourceLocation) -> Json {
+    let line = text.lines().nth(at.line - 1).unwrap_or("");
+    let len = line_words(line).0.into_iter()
+        .find(|(_, column)| *column == at.column)
+        .map_or(1, |(word, _)| word.len());
+    span(line, at, len)
+}
+
+fn utf16_column(line: &str, byte: usize) -> usize {
+    match line.get(..byte) {
+        Some(text) => text.encode_utf16().count(),
+        None => line.encode_utf16().count() + byte.saturating_sub(line.len()),
+    }
+}
+
+fn byte_column(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (index, c) in line.char_indices() {
+        if units >= character {
+            return index;
+        }
+        units += c.len_utf16();
+    }
+    line.len() + character - units
+}
+
+// uri, line and character of a text document position request
+fn position(params: &Json) -> Option<(&str, usize, usize)> {
+    let uri = params.get("textDocument").get("uri").as_str()?;
+    let position = params.get("position");
+    Some((uri, position.get("line").as_usize()?, position.get("character").as_usize()?))
+}
+
+// word of the document that covers a 0 based line and character
+fn word_at(text: &str, line: usize, character: usize) -> Option<(&str, usize)> {
+    let line = text.lines().nth(line)?;
+    let byte = byte_column(line, character);
+    let (words, _) = line_words(line);
+    words.into_iter().find(|(word, column)| (column - 1..column - 1 + word.len()).contains(&byte))
+}
+
+// a label or constant named in a word like "loop:", "[ADDR]" or "A,"
+fn symbol_name(word: &str) -> &str {
+    word.trim_matches(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+}
+
+fn uri_path(uri: &str) -> String {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            },
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            },
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn path_uri(path: &str) -> String {
+    let escaped: String = path.chars()
+        .map(|c| match c {
+            ' ' => "%20".to_string(),
+            '%' => "%25".to_string(),
+            c => c.to_string(),
+        })
+        .collect();
+    format!("file://{}", escaped)
+}
+
+
+#[cfg(test)]
+mod language_server_tests {
+    use std::io::Cursor;
+    use crate::emulator::json::Json;
+    use crate::emulator::isa::Isa;
+    use crate::emulator::language_server::{byte_column, read_message, utf16_column, word_at, write_message, LanguageServer, MAX_MESSAGE};
+    use crate::emulator::td4::Td4;
+
+    const URI: &str = "file:///work/blink%20lamp.sasm";
+
+    fn request(id: usize, method: &str, params: &str) -> Json {
+        Json::parse(&format!(r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#, id, method, params)).unwrap()
+    }
+
+    fn notification(method: &str, params: &str) -> Json {
+        Json::parse(&format!(r#"{{"jsonrpc":"2.0","method":"{}","params":{}}}"#, method, params)).unwrap()
+    }
+
+    fn open(server: &mut LanguageServer, text: &str) -> Json {
+        let params = format!(r#"{{"textDocument":{{"uri":"{}","text":{}}}}}"#, URI, Json::string(text));
+        server.handle(&notification("textDocument/didOpen", &params)).remove(0)
+    }
+
+    fn at(id: usize, method: &str, line: usize, character: usize) -> Json {
+        request(id, method, &format!(
+            r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}}}"#, URI, line, character
+        ))
+    }
+
+    #[test]
+    fn diagnostics_test() {
+        let mut server = LanguageServer::new();
+        let published = open(&mut server, "start: in A\nadd A 0\njmp start\n");
+        assert_eq!(published.get("params").get("uri").as_str(), Some(URI));
+        assert_eq!(
+            published.get("params").get("diagnostics").to_string(),
+            r#"[{"range":{"start":{"line":1,"character":0},"end":{"line":1,"character":3}},"severity":2,"source":"sasm","message":"add A 0000 does not change A","code":"add-zero"}]"#
+        );
+
+        let change = format!(r#"{{"textDocument":{{"uri":"{}"}},"contentChanges":[{{"text":"mov A 1\njmp nowhere\n"}}]}}"#, URI);
+        let published = server.handle(&notification("textDocument/didChange", &change)).remove(0);
+        let diagnostic = &published.get("params").get("diagnostics").as_array().unwrap()[0];
+        assert_eq!(diagnostic.get("severity").as_usize(), Some(1));
+        assert_eq!(diagnostic.get("range").get("start").get("line").as_usize(), Some(1));
+        assert_eq!(diagnostic.get("message").as_str(), Some("undefined label nowhere"));
+    }
+
+    #[test]
+    fn hover_definition_test() {
+        let mut server = LanguageServer::new();
+        open(&mut server, ".equ STEP 1\nstart: add A STEP\njmp start\n");
+
+        let hover = server.handle(&at(1, "textDocument/hover", 1, 8)).remove(0);
+        let text = hover.get("result").get("contents").get("value").as_str().unwrap().to_string();
+        assert!(text.starts_with("add A imm: adds the immediate to A; carry on overflow, zero on a 0 result\nadd B imm: "), "{}", text);
+        assert!(text.contains("add A B: adds B to A (--extended-alu)"), "{}", text);
+        assert!(text.ends_with("`add A 0001` encodes as `00000001`"), "{}", text);
+
+        let hover = server.handle(&at(2, "textDocument/hover", 1, 14)).remove(0);
+        let text = hover.get("result").get("contents").get("value").as_str().unwrap().to_string();
+        assert!(text.starts_with("`STEP constant 0001`"), "{}", text);
+
+        let definition = server.handle(&at(3, "textDocument/definition", 2, 5)).remove(0);
+        assert_eq!(
+            definition.get("result").to_string(),
+            r#"{"uri":"file:///work/blink%20lamp.sasm","range":{"start":{"line":1,"character":0},"end":{"line":1,"character":5}}}"#
+        );
+        assert_eq!(server.handle(&at(4, "textDocument/definition", 2, 0)).remove(0).get("result"), &Json::Null);
+    }
+
+    #[test]
+    fn configuration_test() {
+        let mut server = LanguageServer::new();
+        let mut long = vec!["out B"; 16].join("\n");
+        long.push_str("\n.ifdef DEBUG\nout 0001\n.endif\nhlt\n");
+        let published = open(&mut server, &long);
+        let diagnostic = &published.get("params").get("diagnostics").as_array().unwrap()[0];
+        assert_eq!(diagnostic.get("message").as_str(), Some("address 16 is past the end of rom (16 words)"));
+
+        let mut server = LanguageServer::new();
+        server.handle(&request(1, "initialize", r#"{"initializationOptions":{"romSize":32,"defines":{"DEBUG":1}}}"#));
+        assert_eq!(open(&mut server, &long).get("params").get("diagnostics"), &Json::Array(Vec::new()));
+        let hover = server.handle(&at(2, "textDocument/hover", 17, 0)).remove(0);
+        let text = hover.get("result").get("contents").get("value").as_str().unwrap().to_string();
+        assert!(text.ends_with("`out 0001` encodes as `10110001`"), "{}", text);
+    }
+
+    #[test]
+    fn utf16_test() {
+        let line = "x: .byte 1 ; é😀 y";
+        assert_eq!(utf16_column(line, 20), 17);
+        assert_eq!(byte_column(line, 17), 20);
+        assert_eq!(byte_column(line, 14), 15);
+        assert_eq!(byte_column("ab", 4), 4);
+        assert_eq!(word_at("; é\nx é y", 1, 4), Some(("y", 6)));
+        assert_eq!(word_at("; é\nx é y", 1, 3), None);
+    }
+
+    #[test]
+    fn completion_symbols_test() {
+        let mut server = LanguageServer::new();
+        open(&mut server, "LIMIT = 0011\nloop: out B\njmp loop\n");
+
+        let completion = server.handle(&at(1, "textDocument/completion", 2, 0)).remove(0);
+        let labels: Vec<&str> = completion.get("result").as_array().unwrap().iter()
+            .filter_map(|item| item.get("label").as_str())
+            .collect();
+        // every mnemonic of the instruction table, once
+        for def in Td4::new().instructions() {
+            assert_eq!(labels.iter().filter(|label| **label == def.mnemonic).count(), 1, "{}", def.mnemonic);
+        }
+        assert!(labels.contains(&"[B]"));
+        assert!(labels.contains(&"LIMIT") && labels.contains(&"loop"));
+
+        let symbols = server.handle(&at(2, "textDocument/documentSymbol", 0, 0)).remove(0);
+        let names: Vec<(&str, usize)> = symbols.get("result").as_array().unwrap().iter()
+            .map(|symbol| (symbol.get("name").as_str().unwrap(), symbol.get("kind").as_usize().unwrap()))
+            .collect();
+        assert_eq!(names, vec![("LIMIT", 14), ("loop", 12)]);
+    }
+
+    #[test]
+    fn run_test() {
+        let mut input = Vec::new();
+        for message in [
+            request(1, "initialize", "{}"),
+            notification("initialized", "{}"),
+            request(2, "workspace/symbol", "{}"),
+            request(3, "shutdown", "null"),
+            notification("exit", "null"),
+        ] {
+            write_message(&mut input, &message).unwrap();
+        }
+
+        let mut output = Vec::new();
+        LanguageServer::new().run(&mut Cursor::new(input), &mut output).unwrap();
+
+        let mut reader = Cursor::new(output);
+        let initialized = read_message(&mut reader).unwrap().unwrap().unwrap();
+        assert_eq!(initialized.get("result").get("capabilities").get("hoverProvider"), &Json::Bool(true));
+        let unknown = read_message(&mut reader).unwrap().unwrap().unwrap();
+        assert_eq!(unknown.get("error").get("message").as_str(), Some("unknown method workspace/symbol"));
+        let shutdown = read_message(&mut reader).unwrap().unwrap().unwrap();
+        assert_eq!(shutdown.to_string(), r#"{"jsonrpc":"2.0","id":3,"result":null}"#);
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn run_broken_message_test() {
+        let mut input = b"Content-Length: 7\r\n\r\n{\"id\":1Content-Length: 2\r\n\r\n\xff\xfe".to_vec();
+        input.extend(format!("Content-Length: {}\r\n\r\n", MAX_MESSAGE + 1).bytes());
+        input.extend(vec![b' '; MAX_MESSAGE + 1]);
+        write_message(&mut input, &request(2, "shutdown", "null")).unwrap();
+
+        let mut output = Vec::new();
+        LanguageServer::new().run(&mut Cursor::new(input), &mut output).unwrap();
+
+        let mut reader = Cursor::new(output);
+        let broken = read_message(&mut reader).unwrap().unwrap().unwrap();
+        assert_eq!(
+            broken.to_string(),
+            r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"json: expected , or } at byte 7"}}"#
+        );
+        let not_utf8 = read_message(&mut reader).unwrap().unwrap().unwrap();
+        assert_eq!(not_utf8.get("error").get("message").as_str(), Some("message is not utf-8"));
+        let too_long = read_message(&mut reader).unwrap().unwrap().unwrap();
+        assert_eq!(too_long.get("error").get("code").as_i64(), Some(-32700));
+        assert_eq!(too_long.get("error").get("message").as_str(), Some("message of 1048577 bytes is longer than 1048576 bytes"));
+        let shutdown = read_message(&mut reader).unwrap().unwrap().unwrap();
+        assert_eq!(shutdown.get("id").as_usize(), Some(2));
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::emulator::instructions::{Address, AluOp, Token};
+use crate::emulator::isa::Isa;
+use crate::emulator::machine::INTERRUPT_VECTOR;
+use crate::emulator::parser::Parser;
+use crate::emulator::register::RegisterOp;
+use crate::emulator::source_map::SourceLocation;
+use crate::emulator::td4::Td4;
+
+// lint ids; "; lint: allow id" on a line silences it there, several ids are
+// separated by spaces or commas
+pub const UNREACHABLE: &str = "unreachable";
+pub const JNC_ALWAYS: &str = "jnc-always";
+pub const ADD_ZERO: &str = "add-zero";
+pub const DEAD_WRITE: &str = "dead-write";
+pub const SELF_LOOP: &str = "self-loop";
+
+// code that assembles but likely does not do what was meant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub id: &'static str,
+    pub location: SourceLocation,
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} [{}]", self.location, self.message, self.id)
+    }
+}
+
+#[derive(Debug)]
+pub struct Linter<I: Isa = Td4> {
+    isa: I,
+}
+
+impl Linter {
+    pub fn new() -> Linter {
+        Linter::with_isa(Td4::new())
+    }
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the instructions of a parse and how control moves between them
+struct Program<'a> {
+    tokens: &'a [Token],
+    addresses: Vec<usize>,
+    targets: Vec<Option<usize>>,
+    // token index of the instruction at each rom address
+    starts: HashMap<usize, usize>,
+    interrupts: bool,
+}
+
+impl Program<'_> {
+    // instruction run after index when it does not jump
+    fn next(&self, index: usize) -> Option<usize> {
+        let token = &self.tokens[index];
+        let falls = match token {
+            Token::Jmp(_) | Token::Ret | Token::Reti => false,
+            // an interrupt wakes a halted cpu and its reti comes back after the hlt
+            Token::Hlt => self.interrupts,
+            _ => true,
+        };
+        if !falls {
+            return None;
+        }
+        self.starts.get(&(self.addresses[index] + token.size())).copied()
+    }
+
+    // instructions run after index, jumping or not
+    fn successors(&self, index: usize) -> Vec<usize> {
+        let target = self.targets[index].and_then(|target| self.starts.get(&target).copied());
+        self.next(index).into_iter().chain(target).collect()
+    }
+}
+
+impl<I: Isa> Linter<I> {
+    pub fn with_isa(isa: I) -> Self {
+        Linter { isa }
+    }
+
+    // lints of tokens from parser.parse() not allowed by a comment on their line
+    pub fn lint(&self, parser: &Parser<I>, tokens: &[Token]) -> Vec<Lint> {
+        let addresses = Parser::<I>::addresses(tokens);
+        let starts = tokens.iter().enumerate()
+            .filter(|(_, token)| token.is_instruction())
+            .map(|(index, _)| (addresses[index], index))
+            .collect();
+        let program = Program {
+            tokens,
+            addresses,
+            targets: parser.jump_targets(tokens),
+            starts,
+            interrupts: tokens.iter().any(|token| matches!(token, Token::Ei)),
+        };
+
+        let mut lints = Vec::new();
+        self.unreachable(&program, &mut lints);
+        for (index, token) in tokens.iter().enumerate() {
+            self.jnc_always(&program, index, &mut lints);
+            if let Token::Add(register, 0) = token {
+                lints.push((index, ADD_ZERO, format!(
+                    "add {:?} 0000 does not change {:?}", register, register
+                )));
+            }
+            self.dead_write(&program, index, &mut lints);
+            self.self_loop(&program, index, &mut lints);
+        }
+
+        lints.sort_by_key(|(index, _, _)| *index);
+        lints.into_iter()
+            .map(|(index, id, message)| Lint { id, location: parser.locations()[index].clone(), message })
+            .filter(|lint| !allowed(parser.comment(&lint.location), lint.id))
+            .collect()
+    }
+
+    fn text(&self, token: &Token) -> String {
+        self.isa.disassemble(token).unwrap_or_else(|_| format!("{:?}", token))
+    }
+
+    // code no path from reset or the interrupt vector runs, once per stretch
+    fn unreachable(&self, program: &Program, lints: &mut Vec<(usize, &'static str, String)>) {
+        let mut reached = HashSet::new();
+        let mut pending: Vec<usize> = program.starts.get(&0).copied().into_iter().collect();
+        if program.interrupts {
+            pending.extend(program.starts.get(&(INTERRUPT_VECTOR as usize)));
+        }
+        while let Some(index) = pending.pop() {
+            if reached.insert(index) {
+                pending.extend(program.successors(index));
+            }
+        }
+
+        let mut order: Vec<usize> = program.starts.values().copied().collect();
+        order.sort_by_key(|index| program.addresses[*index]);
+        let mut previous_reached = true;
+        for index in order {
+            let is_reached = reached.contains(&index);
+            if !is_reached && previous_reached {
+                lints.push((index, UNREACHABLE, format!(
+                    "{} can never run", self.text(&program.tokens[index])
+                )));
+            }
+            previous_reached = is_reached;
+        }
+    }
+
+    // jnc only reached straight after an instruction that clears carry always jumps
+    fn jnc_always(&self, program: &Program, index: usize, lints: &mut Vec<(usize, &'static str, String)>) {
+        if !matches!(program.tokens[index], Token::Jnc(_)) {
+            return;
+        }
+        let address = program.addresses[index];
+        let jumped_to = program.targets.contains(&Some(address))
+            || (program.interrupts && address == INTERRUPT_VECTOR as usize);
+        let previous = program.starts.values()
+            .find(|previous| program.next(**previous) == Some(index))
+            .copied();
+        if let Some(previous) = previous.filter(|_| !jumped_to) {
+            if clears_carry(&program.tokens[previous]) {
+                let text = self.text(&program.tokens[previous]);
+                lints.push((index, JNC_ALWAYS, format!(
+                    "{} always jumps, {} before it clears carry", self.text(&program.tokens[index]), text
+                )));
+            }
+        }
+    }
+
+    // a register set by a move or load that is set again before anything reads it
+    fn dead_write(&self, program: &Program, index: usize, lints: &mut Vec<(usize, &'static str, String)>) {
+        let token = &program.tokens[index];
+        // arithmetic also sets the flags, only plain writes are checked
+        if !matches!(token, Token::Mov(_, _) | Token::MovAB | Token::MovBA | Token::In(_) | Token::Ld(_)) {
+            return;
+        }
+        let (_, writes) = registers(token);
+        for register in [RegisterOp::A, RegisterOp::B] {
+            if !writes.contains(&register) {
+                continue;
+            }
+            let mut next = program.next(index);
+            while let Some(later) = next {
+                let later_token = &program.tokens[later];
+                let (reads, writes) = registers(later_token);
+                if reads.contains(&register) || program.targets[later].is_some() || is_control(later_token) {
+                    break;
+                }
+                if writes.contains(&register) {
+                    lints.push((index, DEAD_WRITE, format!(
+                        "{} sets {:?}, but {} sets it again before it is read",
+                        self.text(token), register, self.text(later_token)
+                    )));
+                    break;
+                }
+                next = program.next(later);
+            }
+        }
+    }
+
+    // jmp back over code that neither does i/o nor can leave the loop
+    fn self_loop(&self, program: &Program, index: usize, lints: &mut Vec<(usize, &'static str, String)>) {
+        let token = &program.tokens[index];
+        if !matches!(token, Token::Jmp(_)) {
+            return;
+        }
+        let Some(mut current) = program.targets[index].and_then(|target| program.starts.get(&target).copied()) else {
+            return;
+        };
+        if program.addresses[current] > program.addresses[index] {
+            return;
+        }
+        while current != index {
+            let current_token = &program.tokens[current];
+            if is_io(current_token) || is_control(current_token) || program.targets[current].is_some() {
+                return;
+            }
+            match program.next(current) {
+                Some(next) => current = next,
+                None => return,
+            }
+        }
+        lints.push((index, SELF_LOOP, format!(
+            "{} loops forever without i/o, hlt stops the cpu instead", self.text(token)
+        )));
+    }
+}
+
+fn allowed(comment: Option<&str>, id: &str) -> bool {
+    comment
+        .and_then(|comment| comment.split_once("lint: allow"))
+        .is_some_and(|(_, ids)| ids.split([' ', ',']).any(|allowed| allowed == id))
+}
+
+// instructions that leave the straight line of code or stop the cpu
+fn is_control(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Jmp(_) | Token::Jnc(_) | Token::Jz(_) | Token::Jnz(_) | Token::Call(_)
+            | Token::Ret | Token::Reti | Token::Hlt
+    )
+}
+
+fn is_io(token: &Token) -> bool {
+    matches!(token, Token::In(_) | Token::OutB | Token::OutIm(_) | Token::Ld(_) | Token::St(_))
+}
+
+// every instruction the executor runs with set_carry_flag(0): moves, i/o,
+// memory, jumps and branches taken or not, call and ret, ei and di; after a
+// call the ret that comes back has cleared it. reti is not one, it puts back
+// the carry the interrupt saved
+fn clears_carry(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Mov(_, _) | Token::MovAB | Token::MovBA | Token::In(_) | Token::OutB | Token::OutIm(_)
+            | Token::Ld(_) | Token::St(_) | Token::Jmp(_) | Token::Jnc(_) | Token::Jz(_) | Token::Jnz(_)
+            | Token::Call(_) | Token::Ret | Token::Ei | Token::Di
+    )
+}
+
+// registers the instruction reads and the ones it writes
+fn registers(token: &Token) -> (Vec<RegisterOp>, Vec<RegisterOp>) {
+    let other = |register: &RegisterOp| match register {
+        RegisterOp::A => RegisterOp::B,
+        RegisterOp::B => RegisterOp::A,
+    };
+    match token {
+        Token::Add(register, _) | Token::Sub(register, _) => (vec![*register], vec![*register]),
+        Token::Cmp(register, _) => (vec![*register], vec![]),
+        Token::Mov(register, _) | Token::In(register) => (vec![], vec![*register]),
+        Token::MovAB => (vec![RegisterOp::B], vec![RegisterOp::A]),
+        Token::MovBA => (vec![RegisterOp::A], vec![RegisterOp::B]),
+        Token::OutB => (vec![RegisterOp::B], vec![]),
+        Token::Ld(Address::Direct(_)) => (vec![], vec![RegisterOp::A]),
+        Token::Ld(Address::IndexB) => (vec![RegisterOp::B], vec![RegisterOp::A]),
+        Token::St(Address::Direct(_)) => (vec![RegisterOp::A], vec![]),
+        Token::St(Address::IndexB) => (vec![RegisterOp::A, RegisterOp::B], vec![]),
+        Token::Alu(AluOp::Add | AluOp::And | AluOp::Or | AluOp::Xor, register) => {
+            (vec![*register, other(register)], vec![*register])
+        },
+        Token::Alu(_, register) => (vec![*register], vec![*register]),
+        _ => (vec![], vec![]),
+    }
+}
+
+
+#[cfg(test)]
+mod lint_tests {
+    use crate::emulator::instructions::Token;
+    use crate::emulator::lint::{clears_carry, Lint, Linter};
+    use crate::emulator::parser::Parser;
+
+    fn lint(source: &str) -> Vec<String> {
+        let mut code = Parser::with_file_name("test.sasm", source.lines().map(String::from).collect());
+        let tokens = code.parse().unwrap();
+        Linter::new().lint(&code, &tokens).iter().map(Lint::to_string).collect()
+    }
+
+    #[test]
+    fn lint_test() {
+        assert_eq!(lint("top: in A\nadd A 1\njnc top\nout B\nhlt"), Vec::<String>::new());
+        assert_eq!(
+            lint("mov A 0001\njnc end\nout 0001\nend: hlt"),
+            vec![
+                "test.sasm:2:1: jnc 0011 always jumps, mov A 0001 before it clears carry [jnc-always]",
+            ]
+        );
+        assert_eq!(
+            lint("hlt\nout 0001\nout 0010\nhlt"),
+            vec!["test.sasm:2:1: out 0001 can never run [unreachable]"]
+        );
+        assert_eq!(
+            lint("add B 0\nout B\nhlt"),
+            vec!["test.sasm:1:1: add B 0000 does not change B [add-zero]"]
+        );
+        assert_eq!(
+            lint("mov A 0001\nmov B 0010\nmov A 0011\nout B\nhlt"),
+            vec!["test.sasm:1:1: mov A 0001 sets A, but mov A 0011 sets it again before it is read [dead-write]"]
+        );
+        assert_eq!(lint("mov A 0001\nmov A B\nmov A 0011\nout B\nhlt"), Vec::<String>::new());
+        assert_eq!(
+            lint("out 0001\nloop: add A 1\njmp loop"),
+            vec!["test.sasm:3:1: jmp 0001 loops forever without i/o, hlt stops the cpu instead [self-loop]"]
+        );
+        assert_eq!(lint("loop: out B\nadd B 1\njmp loop"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn clears_carry_test() {
+        let jnc_always = |instruction: &str| {
+            // the nop keeps the jnc off the interrupt vector
+            lint(&format!("nop\n{}\njnc end\nout 0001\nend: hlt", instruction))
+                .iter()
+                .any(|lint| lint.ends_with("[jnc-always]"))
+        };
+        let clearing = [
+            "mov A 0001", "mov B 0001", "mov A B", "mov B A", "in A", "in B", "out B", "out 0001",
+            "ld A [00000001]", "st A [00000001]", "ld A [B]", "st A [B]",
+            "jnc end", "jz end", "jnz end", "call end", "ei", "di",
+        ];
+        for instruction in clearing {
+            assert!(jnc_always(instruction), "{}", instruction);
+        }
+        for instruction in ["add A 0001", "sub A 0001", "cmp A 0001", "add A B", "shl A", "nop", "bank 0001"] {
+            assert!(!jnc_always(instruction), "{}", instruction);
+        }
+
+        // jmp, ret and reti never fall through to a jnc, only the first two clear carry
+        assert!(clears_carry(&Token::Jmp(0)));
+        assert!(clears_carry(&Token::Ret));
+        assert!(!clears_carry(&Token::Reti));
+    }
+
+    #[test]
+    fn lint_allow_test() {
+        assert_eq!(lint("add A 0 ; lint: allow add-zero\nhlt"), Vec::<String>::new());
+        assert_eq!(
+            lint("mov A 1 ; lint: allow dead-write, add-zero\nmov A 0\nadd A 0 ; lint: allow dead-write\nhlt"),
+            vec!["test.sasm:3:1: add A 0000 does not change A [add-zero]"]
+        );
+        assert_eq!(lint("loop: jmp loop ; waits for an interrupt ; lint: allow self-loop"), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::emulator::adapter::BANK_SIZE;
+use crate::EmulatorErr;
+
+// widest program counter the register file can hold
+pub const MAX_ADDRESS_WIDTH: u8 = 16;
+
+// ld/st take their address from one 8 bit rom word
+pub const MAX_RAM_SIZE: usize = 256;
+
+// the bank register is 4 bits wide
+pub const MAX_ROM_BANKS: usize = 16;
+
+pub const MAX_STACK_DEPTH: usize = 256;
+
+// an accepted interrupt jumps here; address 0 is left for a jump over the handler
+pub const INTERRUPT_VECTOR: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineConfig {
+    rom_size: usize,
+    address_width: u8,
+    ram_size: usize,
+    rom_banks: usize,
+    bank_port_base: Option<u8>,
+    extended_alu: bool,
+    stack_depth: usize,
+    stack_wrap: bool,
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self::td4()
+    }
+}
+
+impl MachineConfig {
+    pub fn new(rom_size: usize, address_width: u8) -> Result<Self, EmulatorErr> {
+        let config = Self { rom_size, address_width, ram_size: 0, rom_banks: 1, bank_port_base: None, extended_alu: false, stack_depth: 4, stack_wrap: false };
+        config.validate()?;
+        Ok(config)
+    }
+
+    // the original TD4: 16 words of rom and a 4 bit program counter
+    pub fn td4() -> Self {
+        Self { rom_size: 16, address_width: 4, ram_size: 0, rom_banks: 1, bank_port_base: None, extended_alu: false, stack_depth: 4, stack_wrap: false }
+    }
+
+    pub fn rom_size(&self) -> usize {
+        self.rom_size
+    }
+
+    pub fn address_width(&self) -> u8 {
+        self.address_width
+    }
+
+    // 0 means the machine has no data RAM
+    pub fn ram_size(&self) -> usize {
+        self.ram_size
+    }
+
+    pub fn set_ram_size(&mut self, ram_size: usize) {
+        self.ram_size = ram_size
+    }
+
+    // 1 means no bank switching: jumps reach the first 16 words
+    pub fn rom_banks(&self) -> usize {
+        self.rom_banks
+    }
+
+    pub fn set_rom_banks(&mut self, rom_banks: usize) {
+        self.rom_banks = rom_banks
+    }
+
+    pub fn banked(&self) -> bool {
+        self.rom_banks > 1
+    }
+
+    // output values from base upwards select bank (value - base) instead of driving the port
+    pub fn bank_port_base(&self) -> Option<u8> {
+        self.bank_port_base
+    }
+
+    pub fn set_bank_port_base(&mut self, base: Option<u8>) {
+        self.bank_port_base = base
+    }
+
+    // register to register alu instructions; off for the classic TD4
+    pub fn extended_alu(&self) -> bool {
+        self.extended_alu
+    }
+
+    pub fn set_extended_alu(&mut self, extended_alu: bool) {
+        self.extended_alu = extended_alu
+    }
+
+    // return addresses call can push; 0 means no call/ret
+    pub fn stack_depth(&self) -> usize {
+        self.stack_depth
+    }
+
+    pub fn set_stack_depth(&mut self, stack_depth: usize) {
+        self.stack_depth = stack_depth
+    }
+
+    // full stack drops its oldest entry and ret on an empty one goes to 0, instead of an error
+    pub fn stack_wrap(&self) -> bool {
+        self.stack_wrap
+    }
+
+    pub fn set_stack_wrap(&mut self, stack_wrap: bool) {
+        self.stack_wrap = stack_wrap
+    }
+
+    pub fn pc_mask(&self) -> u16 {
+        ((1u32 << self.address_width) - 1) as u16
+    }
+
+    pub fn validate(&self) -> Result<(), EmulatorErr> {
+        if self.address_width == 0 || self.address_width > MAX_ADDRESS_WIDTH {
+            return Err(EmulatorErr::new(&format!(
+                "Invalid address width {}: must be 1 to {} bits",
+                self.address_width, MAX_ADDRESS_WIDTH
+            )));
+        }
+
+        let addressable = 1usize << self.address_width;
+        if self.rom_size == 0 || self.rom_size > addressable {
+            return Err(EmulatorErr::new(&format!(
+                "Invalid rom size {}: a {} bit address reaches 1 to {} words",
+                self.rom_size, self.address_width, addressable
+            )));
+        }
+
+        if self.ram_size > MAX_RAM_SIZE {
+            return Err(EmulatorErr::new(&format!(
+                "Invalid ram size {}: maximum is {} words",
+                self.ram_size, MAX_RAM_SIZE
+            )));
+        }
+
+        if self.rom_banks == 0 || self.rom_banks > MAX_ROM_BANKS {
+            return Err(EmulatorErr::new(&format!(
+                "Invalid rom bank count {}: must be 1 to {}",
+                self.rom_banks, MAX_ROM_BANKS
+            )));
+        }
+
+        if self.rom_banks * BANK_SIZE > self.rom_size && self.banked() {
+            return Err(EmulatorErr::new(&format!(
+                "Invalid rom bank count {}: {} banks need {} words of rom, rom size is {}",
+                self.rom_banks, self.rom_banks, self.rom_banks * BANK_SIZE, self.rom_size
+            )));
+        }
+
+        if self.stack_depth > MAX_STACK_DEPTH {
+            return Err(EmulatorErr::new(&format!(
+                "Invalid stack depth {}: maximum is {}",
+                self.stack_depth, MAX_STACK_DEPTH
+            )));
+        }
+
+        if self.bank_port_base.is_some() && !self.banked() {
+            return Err(EmulatorErr::new("Bank select port needs more than one rom bank"));
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod machine_tests {
+    use crate::emulator::machine::MachineConfig;
+
+    #[test]
+    fn td4_config_test() {
+        let config = MachineConfig::td4();
+        assert_eq!(config.rom_size(), 16);
+        assert_eq!(config.address_width(), 4);
+        assert_eq!(config.pc_mask(), 0x0f);
+        assert!(!config.extended_alu());
+        assert_eq!(config.stack_depth(), 4);
+        assert!(!config.stack_wrap());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn config_sizes_test() {
+        assert_eq!(MachineConfig::new(256, 8).unwrap().pc_mask(), 0xff);
+        assert_eq!(MachineConfig::new(4096, 12).unwrap().pc_mask(), 0x0fff);
+        assert_eq!(MachineConfig::new(100, 16).unwrap().pc_mask(), 0xffff);
+    }
+
+    #[test]
+    fn invalid_config_test() {
+        assert!(MachineConfig::new(16, 0).is_err());
+        assert!(MachineConfig::new(16, 17).is_err());
+        assert!(MachineConfig::new(0, 4).is_err());
+        assert!(MachineConfig::new(32, 4).is_err());
+
+        let mut config = MachineConfig::td4();
+        config.set_ram_size(256);
+        assert!(config.validate().is_ok());
+        config.set_ram_size(257);
+        assert!(config.validate().is_err());
+
+        let mut config = MachineConfig::td4();
+        config.set_stack_depth(257);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn bank_config_test() {
+        let mut config = MachineConfig::new(64, 6).unwrap();
+        config.set_rom_banks(4);
+        assert!(config.validate().is_ok());
+        config.set_bank_port_base(Some(0b1100));
+        assert!(config.validate().is_ok());
+
+        config.set_rom_banks(5);
+        assert!(config.validate().is_err());
+
+        let mut config = MachineConfig::td4();
+        config.set_bank_port_base(Some(0b1100));
+        assert!(config.validate().is_err());
+    }
+}
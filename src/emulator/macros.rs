@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use crate::emulator::source_map::SourceLocation;
+
+// deepest a macro may call other macros
+pub const MACRO_DEPTH: usize = 16;
+
+// a .macro definition: the words of its body and the source line each is on
+#[derive(Debug, Clone)]
+pub struct Macro {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<(String, usize)>,
+}
+
+// one use of a macro: the call as written and where it was written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub name: String,
+    pub text: String,
+    pub location: SourceLocation,
+}
+
+impl Macro {
+    pub fn new(name: &str, params: Vec<String>, body: Vec<(String, usize)>) -> Self {
+        Macro { name: name.to_string(), params, body }
+    }
+
+    // labels the body defines, renamed in every expansion
+    pub fn labels(&self) -> Vec<&str> {
+        self.body.iter()
+            .filter_map(|(word, _)| word.strip_suffix(':'))
+            .collect()
+    }
+
+    // body words with parameters replaced by the arguments and local labels
+    // by a name unique to the expansion number
+    pub fn instantiate(&self, args: &[String], expansion: usize) -> Vec<(String, usize)> {
+        let mut names: HashMap<&str, String> = self.params.iter()
+            .map(|p| p.as_str())
+            .zip(args.iter().cloned())
+            .collect();
+        for label in self.labels() {
+            names.insert(label, format!("{}__{}{}", label, self.name, expansion));
+        }
+
+        self.body.iter()
+            .map(|(word, line)| (substitute(word, &names), *line))
+            .collect()
+    }
+}
+
+// replace every whole name in word; a replacement that is an expression gets
+// parentheses when it is only part of the word
+pub fn substitute(word: &str, names: &HashMap<&str, String>) -> String {
+    let identifier = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut result = String::new();
+    let mut rest = word;
+
+    while let Some(c) = rest.chars().next() {
+        if !identifier(c) {
+            result.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let len = rest.find(|c: char| !identifier(c)).unwrap_or(rest.len());
+        let name = &rest[..len];
+        match names.get(name) {
+            Some(value) if !c.is_ascii_digit() => {
+                if len < word.len() && !value.chars().all(identifier) {
+                    result.push_str(&format!("({})", value));
+                } else {
+                    result.push_str(value);
+                }
+            },
+            _ => result.push_str(name),
+        }
+        rest = &rest[len..];
+    }
+
+    result
+}
+
+
+#[cfg(test)]
+mod macros_tests {
+    use std::collections::HashMap;
+    use crate::emulator::macros::{substitute, Macro};
+
+    #[test]
+    fn instantiate_test() {
+        let body = vec![
+            ("loop:", 1), ("add", 1), ("REG", 1), ("1111", 1),
+            ("jnc", 2), ("loop", 2),
+            ("out", 3), ("COUNT-1", 3),
+        ];
+        let wait = Macro::new(
+            "wait",
+            vec!["REG".to_string(), "COUNT".to_string()],
+            body.into_iter().map(|(w, l)| (w.to_string(), l)).collect(),
+        );
+
+        let words: Vec<String> = wait.instantiate(&["A".to_string(), "N+1".to_string()], 2)
+            .into_iter()
+            .map(|(w, _)| w)
+            .collect();
+        assert_eq!(words, vec!["loop__wait2:", "add", "A", "1111", "jnc", "loop__wait2", "out", "(N+1)-1"]);
+    }
+
+    #[test]
+    fn substitute_test() {
+        let names = HashMap::from([("ADDR", "00010000".to_string()), ("X", "0b1".to_string())]);
+        assert_eq!(substitute("[ADDR]", &names), "[00010000]");
+        assert_eq!(substitute("ADDR_2", &names), "ADDR_2");
+        assert_eq!(substitute("0xX", &names), "0xX");
+        assert_eq!(substitute("X<<1", &names), "0b1<<1");
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+use crate::EmulatorErr;
+
+// ld/st address one 8 bit rom word, so the data address space is 256 words
+pub const ADDRESS_SPACE: usize = 256;
+
+// a peripheral reachable through ld/st once it is mapped
+pub trait Device {
+    fn name(&self) -> &str;
+    fn read(&mut self, offset: usize) -> Result<u8, EmulatorErr>;
+    fn write(&mut self, offset: usize, value: u8) -> Result<(), EmulatorErr>;
+
+    // asked once before every instruction; true raises the interrupt line
+    fn interrupt(&mut self) -> bool {
+        false
+    }
+
+    // called once after every executed instruction
+    fn tick(&mut self) {}
+
+    // input lines the device drives, ored into what in A / in B read
+    fn input(&self) -> u8 {
+        0
+    }
+}
+
+pub enum Target {
+    Ram { base: usize },
+    Rom { base: usize },
+    Port,
+    Device(Box<dyn Device>),
+}
+
+impl fmt::Debug for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Ram { base } => write!(f, "ram+{}", base),
+            Target::Rom { base } => write!(f, "rom+{}", base),
+            Target::Port => write!(f, "port"),
+            Target::Device(device) => write!(f, "{}", device.name()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Mapping {
+    pub start: usize,
+    pub size: usize,
+    pub target: Target,
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryMap {
+    mappings: Vec<Mapping>,
+}
+
+impl MemoryMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // ram from address 0, the layout of a machine without an explicit map
+    pub fn with_ram(ram_size: usize) -> Self {
+        let mut map = Self::new();
+        if ram_size > 0 {
+            map.map(0, ram_size, Target::Ram { base: 0 }).unwrap();
+        }
+        map
+    }
+
+    pub fn map(&mut self, start: usize, size: usize, target: Target) -> Result<(), EmulatorErr> {
+        let end = start + size;
+        if size == 0 || end > ADDRESS_SPACE {
+            return Err(EmulatorErr::new(&format!(
+                "Invalid mapping {}..{}: must be inside 0..{}",
+                start, end, ADDRESS_SPACE
+            )));
+        }
+
+        if let Some(other) = self.mappings.iter().find(|m| start < m.start + m.size && m.start < end) {
+            return Err(EmulatorErr::new(&format!(
+                "Mapping {}..{} overlaps {:?} at {}..{}",
+                start, end, other.target, other.start, other.start + other.size
+            )));
+        }
+
+        self.mappings.push(Mapping { start, size, target });
+        Ok(())
+    }
+
+    pub fn map_ram(&mut self, start: usize, size: usize, base: usize) -> Result<(), EmulatorErr> {
+        self.map(start, size, Target::Ram { base })
+    }
+
+    pub fn map_rom(&mut self, start: usize, size: usize, base: usize) -> Result<(), EmulatorErr> {
+        self.map(start, size, Target::Rom { base })
+    }
+
+    pub fn map_port(&mut self, address: usize) -> Result<(), EmulatorErr> {
+        self.map(address, 1, Target::Port)
+    }
+
+    pub fn map_device(&mut self, start: usize, size: usize, device: Box<dyn Device>) -> Result<(), EmulatorErr> {
+        self.map(start, size, Target::Device(device))
+    }
+
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    pub fn tick_devices(&mut self) {
+        for mapping in self.mappings.iter_mut() {
+            if let Target::Device(device) = &mut mapping.target {
+                device.tick();
+            }
+        }
+    }
+
+    pub fn device_input(&self) -> u8 {
+        self.mappings.iter()
+            .filter_map(|m| match &m.target {
+                Target::Device(device) => Some(device.input()),
+                _ => None,
+            })
+            .fold(0, |input, lines| input | lines)
+    }
+
+    // true when any mapped device requests an interrupt
+    pub fn poll_interrupts(&mut self) -> bool {
+        let mut request = false;
+        for mapping in self.mappings.iter_mut() {
+            if let Target::Device(device) = &mut mapping.target {
+                request |= device.interrupt();
+            }
+        }
+        request
+    }
+
+    // mapping target and offset inside it for an address
+    pub fn resolve(&mut self, address: usize) -> Result<(&mut Target, usize), EmulatorErr> {
+        self.mappings.iter_mut()
+            .find(|m| m.start <= address && address < m.start + m.size)
+            .map(|m| (&mut m.target, address - m.start))
+            .ok_or_else(|| EmulatorErr::new(&format!("Memory address {} is not mapped", address)))
+    }
+}
+
+
+#[cfg(test)]
+mod memory_map_tests {
+    use crate::emulator::memory_map::{MemoryMap, Target};
+
+    #[test]
+    fn resolve_test() {
+        let mut map = MemoryMap::new();
+        map.map_ram(0, 16, 0).unwrap();
+        map.map_rom(0x80, 16, 4).unwrap();
+        map.map_port(0xff).unwrap();
+
+        assert!(matches!(map.resolve(3).unwrap(), (Target::Ram { base: 0 }, 3)));
+        assert!(matches!(map.resolve(0x82).unwrap(), (Target::Rom { base: 4 }, 2)));
+        assert!(matches!(map.resolve(0xff).unwrap(), (Target::Port, 0)));
+        assert!(map.resolve(0x10).is_err());
+    }
+
+    #[test]
+    fn invalid_mapping_test() {
+        let mut map = MemoryMap::with_ram(16);
+        assert!(map.map_port(15).is_err());
+        assert!(map.map_rom(0xf8, 16, 0).is_err());
+        assert!(map.map_ram(0x20, 0, 0).is_err());
+        assert!(map.map_port(16).is_ok());
+    }
+}
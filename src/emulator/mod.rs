@@ -4,4 +4,18 @@ pub mod register;
 pub mod compiler;
 pub mod executor;
 pub mod parser;
-pub mod adapter;
\ No newline at end of file
+pub mod expression;
+pub mod macros;
+pub mod adapter;
+pub mod source_map;
+pub mod machine;
+pub mod snapshot;
+pub mod memory_map;
+pub mod disassembler;
+pub mod formatter;
+pub mod json;
+pub mod language_server;
+pub mod lint;
+pub mod timer;
+pub mod isa;
+pub mod td4;
@@ -1,34 +1,347 @@
 
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use crate::emulator::adapter::BANK_SIZE;
+use crate::emulator::expression;
+use crate::emulator::macros::{Expansion, Macro, MACRO_DEPTH};
 use crate::emulator::instructions::Token;
-use crate::emulator::register::RegisterOp;
-use crate::lib::EmulatorErr;
+use crate::emulator::isa::{InstructionDef, Isa, Operand};
+use crate::emulator::machine::MAX_ADDRESS_WIDTH;
+use crate::emulator::source_map::SourceLocation;
+use crate::emulator::td4::Td4;
+use crate::EmulatorErr;
+
+// token index, instruction, operand expression, its source word and the bank the instruction runs in
+type Fixup<I> = (usize, &'static InstructionDef<I>, String, usize, u8);
+
+// source word, its position and the logical line it is on
+type Word = (String, SourceLocation, usize);
+
+// text after a ; and where the ; is
+type Comment = (String, SourceLocation);
+
+// text in a source line and the column it starts in
+pub type LineWord<'a> = (&'a str, usize);
+
+// words the largest rom configuration addresses; .org, .align and .fill stay inside
+const ADDRESS_SPACE: usize = 1 << MAX_ADDRESS_WIDTH;
+
+// words of a source line split at spaces and tabs, and the text after a ; outside quotes
+pub fn line_words(line: &str) -> (Vec<LineWord<'_>>, Option<LineWord<'_>>) {
+    let mut quoted = false;
+    let start = line.char_indices()
+        .find(|(_, c)| {
+            quoted ^= *c == '"';
+            *c == ';' && !quoted
+        })
+        .map(|(i, _)| i);
+    let (code, comment) = match start {
+        Some(start) => (&line[..start], Some((&line[start + 1..], start + 1))),
+        None => (line, None),
+    };
+
+    let mut words = Vec::new();
+    let mut column = 1;
+    for word in code.split([' ', '\t']) {
+        if !word.is_empty() {
+            words.push((word, column));
+        }
+        column += word.len() + 1;
+    }
+    (words, comment)
+}
+
+// source syntax the parser reads
+//   Native: lowercase mnemonics, operands separated by spaces, mov A B copies A into B
+//   Book:   the book's syntax, case-insensitive, operands separated by commas,
+//           MOV A, B copies B into A
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    #[default]
+    Native,
+    Book,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Label,
+    Constant,
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolKind::Label => write!(f, "label"),
+            SymbolKind::Constant => write!(f, "constant"),
+        }
+    }
+}
+
+// a name the source defines: the rom address of a label or the value of a constant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub value: i64,
+    pub location: SourceLocation,
+}
+
+impl Symbol {
+    // labels as a hex rom address like the listing, constants in binary like the source
+    pub fn value_text(&self) -> String {
+        match self.kind {
+            SymbolKind::Label => format!("{:04x}", self.value),
+            SymbolKind::Constant if self.value < 0 => format!("-{:04b}", -self.value),
+            SymbolKind::Constant => format!("{:04b}", self.value),
+        }
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.name, self.kind, self.value_text())
+    }
+}
+
+// source that parses but may not mean what was meant, and where it is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub location: Option<SourceLocation>,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{}: {}", location, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = EmulatorErr;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "native" => Ok(Dialect::Native),
+            "book" => Ok(Dialect::Book),
+            _ => Err(EmulatorErr::new(&format!("unknown dialect {}", name))),
+        }
+    }
+}
 
 #[derive(Debug)]
-pub struct Parser {
+pub struct Parser<I: Isa = Td4> {
     pub index: usize,
     pub opcodes: Vec<String>,
+    pub positions: Vec<SourceLocation>,
+    pub locations: Vec<SourceLocation>,
+    isa: I,
+    dialect: Dialect,
+    warnings: Vec<Warning>,
+    constants: HashMap<String, i64>,
+    symbols: Vec<Symbol>,
+    // logical line of every word, macro expansions get lines of their own
+    lines: Vec<usize>,
+    expansions: Vec<Expansion>,
+    preprocessed: bool,
+    file_name: String,
+    include_paths: Vec<PathBuf>,
+    // constants given from outside the source, -D NAME=value
+    defines: HashMap<String, i64>,
+    comments: Vec<Comment>,
+    // source lines as written, .include takes its file name from them
+    source: Vec<String>,
 }
 
 impl Parser {
     pub fn new(operations: Vec<String>) -> Parser {
+        Parser::with_file_name("", operations)
+    }
+
+    pub fn with_file_name(file_name: &str, operations: Vec<String>) -> Parser {
+        Parser::with_isa(Td4::new(), file_name, operations)
+    }
+}
+
+impl<I: Isa> Parser<I> {
+    // instructions come from the table of isa
+    pub fn with_isa(isa: I, file_name: &str, operations: Vec<String>) -> Self {
+        let mut comments = Vec::new();
+        let words = Self::words(file_name, operations.iter().map(|o| o.as_str()), 0, &mut comments);
+
+        Parser {
+            index: 0,
+            opcodes: words.iter().map(|(word, _, _)| word.clone()).collect(),
+            positions: words.iter().map(|(_, location, _)| location.clone()).collect(),
+            locations: Vec::new(),
+            isa,
+            dialect: Dialect::Native,
+            warnings: Vec::new(),
+            constants: HashMap::new(),
+            symbols: Vec::new(),
+            lines: words.iter().map(|(_, _, line)| *line).collect(),
+            expansions: Vec::new(),
+            preprocessed: false,
+            file_name: file_name.to_string(),
+            include_paths: Vec::new(),
+            defines: HashMap::new(),
+            comments,
+            source: operations,
+        }
+    }
+
+    // words of source lines, logical lines counted from first_line
+    fn words<'a>(
+        file_name: &str,
+        operations: impl Iterator<Item = &'a str>,
+        first_line: usize,
+        comments: &mut Vec<Comment>,
+    ) -> Vec<Word> {
+        let mut words = Vec::new();
+        for (line, operation) in operations.enumerate() {
+            let (line_words, comment) = line_words(operation);
+            for (word, column) in line_words {
+                words.push((word.to_string(), SourceLocation::new(file_name, line + 1, column), first_line + line));
+            }
+            if let Some((text, column)) = comment {
+                comments.push((text.trim().to_string(), SourceLocation::new(file_name, line + 1, column)));
+            }
+        }
+        words
+    }
+
+    // a constant known to .if / .ifdef and to the code, like .equ name value
+    pub fn define(&mut self, name: &str, value: i64) {
+        self.defines.insert(name.to_string(), value);
+    }
+
+    // directories .include searches after the one of the including file
+    pub fn add_include_path(&mut self, path: &str) {
+        self.include_paths.push(PathBuf::from(path));
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    // the book dialect is case-insensitive and separates operands with commas
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    // split words at commas and normalize their case
+    fn book_words(&mut self) {
         let mut opcodes = Vec::new();
+        let mut positions = Vec::new();
+        let mut lines = Vec::new();
+        // directives take comma separated lists, their lines keep the commas
+        let directive_lines: Vec<usize> = self.opcodes.iter().zip(&self.lines)
+            .filter(|(word, _)| word.starts_with('.'))
+            .map(|(_, line)| *line)
+            .collect();
+        // quoted text keeps its case
+        let mut quoted = false;
+        let mut previous_line = None;
+        for ((word, location), line) in self.opcodes.iter().zip(&self.positions).zip(&self.lines) {
+            if previous_line != Some(*line) {
+                quoted = false;
+                previous_line = Some(*line);
+            }
+            let verbatim = quoted || word.contains('"');
+            quoted ^= word.matches('"').count() % 2 == 1;
 
-        for operation in operations {
-            let op: Vec<&str> = operation.split(" ").collect();
-            for o in op {
-                let st = o.to_string();
-                opcodes.push(st);
+            let mut column = location.column;
+            let parts: Vec<&str> = if directive_lines.contains(line) {
+                vec![word.as_str()]
+            } else {
+                word.split(',').collect()
+            };
+            for part in parts {
+                if !part.is_empty() {
+                    opcodes.push(if verbatim { part.to_string() } else { Self::book_word(part) });
+                    positions.push(SourceLocation::new(&location.file, location.line, column));
+                    lines.push(*line);
+                }
+                column += part.len() + 1;
             }
         }
+        self.opcodes = opcodes;
+        self.positions = positions;
+        self.lines = lines;
+    }
 
-        Parser { index: 0, opcodes }
+    // source that parses but may not mean what was meant
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    // source position of each token returned by the last parse()
+    pub fn locations(&self) -> &[SourceLocation] {
+        &self.locations
+    }
+
+    // comments of the source and its include files, in the order they were read
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    // comment on the source line of location
+    pub fn comment(&self, location: &SourceLocation) -> Option<&str> {
+        self.comments.iter()
+            .find(|(_, at)| at.file == location.file && at.line == location.line)
+            .map(|(text, _)| text.as_str())
+    }
+
+    // constants then labels of the last parse(), each in source order
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    // every macro call of the last parse(); the tokens a call expands to are
+    // located at the call
+    pub fn expansions(&self) -> &[Expansion] {
+        &self.expansions
     }
 
     pub fn parse(&mut self) -> Result<Vec<Token>, EmulatorErr> {
         let mut results = Vec::new();
+        self.index = 0;
+        self.locations.clear();
+        self.warnings.clear();
+        self.symbols.clear();
+
+        if !self.preprocessed {
+            self.include_files()?;
+            if self.dialect == Dialect::Book {
+                self.book_words();
+            }
+            self.select_conditionals(true)?;
+            self.expand_macros()?;
+            self.select_conditionals(false)?;
+            if self.dialect == Dialect::Book {
+                for index in 0..self.opcodes.len() {
+                    if self.opcodes[index] == "mov" {
+                        self.swap_registers(index);
+                    }
+                }
+            }
+            self.preprocessed = true;
+        }
+        self.define_constants()?;
+        let label_banks = self.label_banks();
+        let mut labels = HashMap::new();
+        let mut fixups = Vec::new();
+        let mut bank = 0;
+        let mut address: usize = 0;
+        let mut asserts = Vec::new();
 
         loop {
-            let opcode = self.opcodes.get(self.index);
+            let opcode = self.opcodes.get(self.index).cloned();
 
             let opcode = match opcode {
                 Some(op) => op,
@@ -36,119 +349,1606 @@ impl Parser {
                     break
                 }
             };
+            let location = self.positions[self.index].clone();
 
-            match opcode.as_str() {
-                "mov" => {
-                    self.index += 1;
-                    let lhs = self.opcodes.get(self.index).expect("mov instruction left side error");
+            let tokens = match opcode.as_str() {
+                ".bank" => {
+                    let (text, end) = self.rest_of_line(self.index + 1);
+                    if text.is_empty() {
+                        return Err(self.error(".bank directive error"));
+                    }
+                    let value = self.constant_expression(&text, self.index + 1)?;
+                    bank = self.field(Operand::Immediate, value, self.index + 1)?;
+                    self.index = end - 1;
 
-                    self.index += 1;
-                    let rhs = self.opcodes.get(self.index).expect("mov instruction right side error");
+                    vec![Token::Org(bank as usize * BANK_SIZE)]
+                },
+                ".org" | ".align" => {
+                    let (text, end) = self.rest_of_line(self.index + 1);
+                    if text.is_empty() {
+                        return Err(self.error(&format!("{} needs a value", opcode)));
+                    }
+                    let value = self.constant_expression(&text, self.index + 1)?;
+                    let target = match opcode.as_str() {
+                        ".org" if value >= 0 => value as usize,
+                        ".align" if value > 0 => address.next_multiple_of(value as usize),
+                        _ => return Err(self.error_at(self.index + 1, &format!("invalid {} {}", opcode, value))),
+                    };
+                    if target > ADDRESS_SPACE {
+                        return Err(self.error_at(self.index + 1, &format!("{} {} is beyond the address space of {} words", opcode, value, ADDRESS_SPACE)));
+                    }
+                    bank = (target / BANK_SIZE) as u8;
+                    self.index = end - 1;
 
-                    let token =
-                        if lhs == "A" && rhs == "B" {
-                            Token::MovBA
-                        } else if lhs == "B" && rhs == "A" {
-                            Token::MovAB
-                        } else {
-                            Token::Mov(
-                                RegisterOp::from(lhs.to_string()),
-                                self.binary_to_decimal(rhs).unwrap(),
-                            )
-                        };
+                    vec![Token::Org(target)]
+                },
+                // checked once labels have addresses
+                ".assert" => {
+                    let (text, end) = self.rest_of_line(self.index + 1);
+                    let (condition, message) = match text.find('"') {
+                        Some(quote) => {
+                            let condition = text[..quote].trim_end().strip_suffix(',');
+                            let message = text[quote + 1..].strip_suffix('"');
+                            match (condition, message) {
+                                (Some(condition), Some(message)) => (condition.to_string(), message.to_string()),
+                                _ => return Err(self.error(".assert takes a condition and a quoted message")),
+                            }
+                        },
+                        None => (text.clone(), text.clone()),
+                    };
+                    if condition.trim().is_empty() {
+                        return Err(self.error(".assert needs a condition"));
+                    }
+                    asserts.push((condition, message, self.index));
+                    self.index = end - 1;
 
-                    results.push(token)
+                    vec![]
+                },
+                // comma separated values, each one rom word
+                ".byte" => {
+                    let values = self.list(self.index + 1)?;
+                    if values.is_empty() {
+                        return Err(self.error(".byte needs a value"));
+                    }
+                    self.index = self.line_end(self.index) - 1;
+
+                    values.into_iter().map(Token::Byte).collect()
+                },
+                // count words of value, or count words left to the fill value
+                ".fill" => {
+                    let (text, _) = self.rest_of_line(self.index + 1);
+                    if text.is_empty() {
+                        return Err(self.error(".fill needs a count"));
+                    }
+                    let mut parts = text.split(',');
+                    let count = self.constant_expression(parts.next().unwrap_or_default(), self.index + 1)?;
+                    let count = usize::try_from(count)
+                        .map_err(|_| self.error_at(self.index + 1, &format!("invalid .fill count {}", count)))?;
+                    address.checked_add(count)
+                        .filter(|end| *end <= ADDRESS_SPACE)
+                        .ok_or_else(|| self.error_at(self.index + 1, &format!(".fill {} is beyond the address space of {} words", count, ADDRESS_SPACE)))?;
+                    let value = match parts.next() {
+                        Some(text) => {
+                            let value = self.constant_expression(text, self.index + 1)?;
+                            Some(self.field(Operand::Address, value, self.index + 1)?)
+                        },
+                        None => None,
+                    };
+                    if parts.next().is_some() {
+                        return Err(self.error(".fill takes a count and a value"));
+                    }
+                    self.index = self.line_end(self.index) - 1;
 
+                    match value {
+                        Some(value) => (0..count).map(|_| Token::Byte(value)).collect(),
+                        None => vec![Token::Reserve(count)],
+                    }
+                },
+                // defined before the first pass, see define_constants
+                ".equ" => {
+                    self.index = self.line_end(self.index) - 1;
+                    vec![]
                 },
-                "add" => {
-                    self.index += 1;
-                    let lhs = self.opcodes.get(self.index).expect("add instruction left side error");
+                _ if self.opcodes.get(self.index + 1).is_some_and(|w| w == "=") => {
+                    self.index = self.line_end(self.index) - 1;
+                    vec![]
+                },
+                label if label.ends_with(':') => {
+                    let name = &label[..label.len() - 1];
+                    if !self.is_label(name) {
+                        return Err(self.error(&format!("invalid label name {}", name)));
+                    }
+                    if labels.insert(name.to_string(), results.len()).is_some() {
+                        return Err(self.error(&format!("duplicate label {}", name)));
+                    }
+                    self.symbols.push(Symbol {
+                        name: name.to_string(),
+                        kind: SymbolKind::Label,
+                        value: results.len() as i64,
+                        location: location.clone(),
+                    });
 
-                    self.index += 1;
-                    let rhs = self.opcodes.get(self.index).expect("add instruction right side error");
+                    vec![]
+                },
+                mnemonic => {
+                    let (def, operands, end) = self.instruction(mnemonic)?;
+                    if self.dialect == Dialect::Native && mnemonic == "mov" && def.operands.iter().all(|o| o.keyword()) {
+                        let (from, to) = (&operands[0], &operands[1]);
+                        self.warnings.push(Warning {
+                            location: self.positions.get(self.index).cloned(),
+                            message: format!(
+                                "mov {} {} copies {} into {}; the book's MOV {}, {} copies {} into {}",
+                                from, to, from, to, from, to, to, from
+                            ),
+                        });
+                    }
+                    let position = def.operands.iter().position(|o| !o.keyword());
+                    let operand_index = self.index + 1 + position.unwrap_or(0);
+                    self.index = end - 1;
 
-                    let token = Token::Add(
-                        RegisterOp::from(lhs.to_string()),
-                        self.binary_to_decimal(rhs).unwrap(),
-                    );
+                    match (def.value_operand(), position) {
+                        (Some(Operand::Jump), Some(position)) if self.is_label_operand(&operands[position]) => {
+                            let label = operands[position].clone();
+                            let target_bank = *label_banks.get(&label)
+                                .ok_or_else(|| self.error_at(operand_index, &format!("undefined label {}", label)))?;
 
-                    results.push(token)
+                            // a jump into another bank selects it first; an untaken branch selects ours again
+                            let mut tokens = Vec::new();
+                            if target_bank != bank {
+                                tokens.push(Token::Bank(target_bank));
+                            }
+                            fixups.push((results.len() + tokens.len(), def, label, operand_index, target_bank));
+                            tokens.push((def.token)(0));
+                            if target_bank != bank && def.mnemonic != "jmp" {
+                                tokens.push(Token::Bank(bank));
+                            }
+                            tokens
+                        },
+                        (Some(operand), Some(position)) => {
+                            let text = &operands[position];
+                            let inner = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')).unwrap_or(text);
+                            let names = expression::names(inner).map_err(|e| self.error_at(operand_index, e.msg()))?;
 
+                            // an expression naming a label is evaluated once addresses are known
+                            if names.iter().any(|n| label_banks.contains_key(n) && !self.constants.contains_key(n)) {
+                                fixups.push((results.len(), def, inner.to_string(), operand_index, bank));
+                                vec![(def.token)(0)]
+                            } else {
+                                let value = self.constant_expression(inner, operand_index)?;
+                                vec![(def.token)(self.field(operand, value, operand_index)?)]
+                            }
+                        },
+                        _ => vec![(def.token)(0)],
+                    }
                 },
-                "jmp" => {
-                    self.index += 1;
-                    let immediate = self.opcodes.get(self.index).expect("jmp instruction error");
+            };
+
+            for token in tokens {
+                address = match token {
+                    Token::Org(org) => org,
+                    _ => address + token.size(),
+                };
+                results.push(token);
+                self.locations.push(location.clone());
+            }
+            self.index += 1;
+        }
 
-                    let token = Token::Jmp(self.binary_to_decimal(immediate).unwrap());
-                    results.push(token)
+        let addresses = self.resolve_labels(&mut results, &labels, fixups)?;
+        for symbol in self.symbols.iter_mut() {
+            if symbol.kind == SymbolKind::Label {
+                symbol.value = addresses[symbol.value as usize] as i64;
+            }
+        }
 
-                },
-                "jnc" => {
-                    self.index += 1;
-                    let immediate = self.opcodes.get(self.index).expect("jnc instruction error");
+        for (condition, message, index) in asserts {
+            let symbol = |name: &str| {
+                self.constants.get(name).copied()
+                    .or_else(|| labels.get(name).map(|index| addresses[*index] as i64))
+            };
+            let value = expression::evaluate(&condition, &symbol)
+                .map_err(|e| self.error_at(index + 1, e.msg()))?;
+            if value == 0 {
+                return Err(self.error_at(index, &format!("assertion failed: {}", message)));
+            }
+        }
+        Ok(results)
+    }
 
-                    let token = Token::Jnc(self.binary_to_decimal(immediate).unwrap());
-                    results.push(token)
+    // layout checks of parsed tokens for a rom of rom_size words reached by
+    // address_width bit addresses: everything fits, every jump lands on an
+    // instruction and no call needs more than its 8 bit address are errors,
+    // a last instruction that lets execution run on into the fill words is a warning
+    pub fn check_layout(&mut self, tokens: &[Token], rom_size: usize, address_width: u8) -> Result<(), EmulatorErr> {
+        let addresses = Self::addresses(tokens);
+        let instruction = |token: &Token| token.is_instruction();
+        let starts = Self::instruction_addresses(tokens);
+        let located = |index: usize, msg: String| match self.locations.get(index) {
+            Some(location) => EmulatorErr::at(location, &msg),
+            None => EmulatorErr::new(&msg),
+        };
 
-                },
-                "in" => {
-                    self.index += 1;
-                    let immediate = self.opcodes.get(self.index).expect("in instruction error");
+        let targets = self.jump_targets(tokens);
+        for (index, token) in tokens.iter().enumerate() {
+            let address = addresses[index];
+            if address + token.size() > rom_size {
+                return Err(located(index, format!(
+                    "address {} is past the end of rom ({} words)", address + token.size() - 1, rom_size
+                )));
+            }
+
+            if matches!(token, Token::Call(_)) && address_width > 8 {
+                return Err(located(index, format!(
+                    "call takes an 8 bit address, this machine has {} bit addresses", address_width
+                )));
+            }
 
-                    let token = Token::In(
-                        RegisterOp::from(immediate.to_string())
-                    );
+            let Some(target) = targets[index] else { continue };
+            if !starts.contains(&target) {
+                let text = self.isa.disassemble(token)?;
+                return Err(located(index, format!("{} goes to address {}, which is not an instruction", text, target)));
+            }
+        }
 
-                    results.push(token)
+        let last = tokens.iter().enumerate()
+            .filter(|(_, token)| instruction(token))
+            .max_by_key(|(index, _)| addresses[*index]);
+        if let Some((index, token)) = last {
+            if !matches!(token, Token::Jmp(_) | Token::Hlt | Token::Ret | Token::Reti) {
+                let text = self.isa.disassemble(token)?;
+                self.warnings.push(Warning {
+                    location: self.locations.get(index).cloned(),
+                    message: format!("execution runs past the last instruction {} into the fill words", text),
+                });
+            }
+        }
+        Ok(())
+    }
 
+    // rom address each jump, branch and call token goes to, None for other tokens;
+    // the bank a jump runs in follows .bank / .org sections and bank instructions in source order
+    pub fn jump_targets(&self, tokens: &[Token]) -> Vec<Option<usize>> {
+        let mut bank = 0;
+        tokens.iter()
+            .map(|token| match token {
+                Token::Org(org) => {
+                    bank = org / BANK_SIZE;
+                    None
                 },
-                "out" => {
-                    self.index += 1;
-                    let immediate = self.opcodes.get(self.index).expect("out instruction error");
+                Token::Bank(value) => {
+                    bank = *value as usize;
+                    None
+                },
+                Token::Byte(_) | Token::Reserve(_) => None,
+                token => match self.isa.lookup(token) {
+                    Ok((def, value)) if def.value_operand() == Some(Operand::Jump) => Some(bank * BANK_SIZE + value as usize),
+                    Ok((def, value)) if def.value_operand() == Some(Operand::Address) => Some(value as usize),
+                    _ => None,
+                },
+            })
+            .collect()
+    }
+
+    // rom address of the first word of every instruction; .byte data is not one
+    pub fn instruction_addresses(tokens: &[Token]) -> Vec<usize> {
+        tokens.iter().zip(Self::addresses(tokens))
+            .filter(|(token, _)| token.is_instruction())
+            .map(|(_, address)| address)
+            .collect()
+    }
+
+    // rom address of every token, and the one after the last
+    pub fn addresses(tokens: &[Token]) -> Vec<usize> {
+        let mut addresses = Vec::with_capacity(tokens.len() + 1);
+        let mut address = 0;
+        for token in tokens {
+            if let Token::Org(org) = token {
+                address = *org;
+            }
+            addresses.push(address);
+            address += token.size();
+        }
+        addresses.push(address);
+        addresses
+    }
+
+    // replace every .include "file" line with the words of the file, searched
+    // next to the including file, then in the include paths
+    fn include_files(&mut self) -> Result<(), EmulatorErr> {
+        let words: Vec<Word> = self.opcodes.iter().cloned()
+            .zip(self.positions.iter().cloned())
+            .zip(self.lines.iter().copied())
+            .map(|((word, location), line)| (word, location, line))
+            .collect();
+
+        let mut stack = Vec::new();
+        if let Ok(path) = fs::canonicalize(&self.file_name) {
+            stack.push((path, self.file_name.clone()));
+        }
+        let mut next_line = self.lines.iter().max().map_or(0, |line| line + 1);
+        let mut comments = Vec::new();
+        let lines: Vec<&str> = self.source.iter().map(String::as_str).collect();
+        let words = self.include_words(words, &lines, &mut stack, &mut next_line, &mut comments)?;
+        self.comments.extend(comments);
+
+        self.opcodes = words.iter().map(|(word, _, _)| word.clone()).collect();
+        self.positions = words.iter().map(|(_, location, _)| location.clone()).collect();
+        self.lines = words.iter().map(|(_, _, line)| *line).collect();
+        Ok(())
+    }
+
+    // stack holds the files being included, outermost first; lines are the
+    // source lines of the file words come from
+    fn include_words(
+        &self,
+        words: Vec<Word>,
+        lines: &[&str],
+        stack: &mut Vec<(PathBuf, String)>,
+        next_line: &mut usize,
+        comments: &mut Vec<Comment>,
+    ) -> Result<Vec<Word>, EmulatorErr> {
+        let mut output = Vec::new();
+
+        let mut index = 0;
+        while index < words.len() {
+            let (word, location, line) = &words[index];
+            let include = word == ".include" || (self.dialect == Dialect::Book && word.eq_ignore_ascii_case(".include"));
+            if !include {
+                output.push(words[index].clone());
+                index += 1;
+                continue;
+            }
+
+            let error = |msg: String| EmulatorErr::at(location, &msg);
+            let end = index + words[index..].iter().take_while(|w| w.2 == *line).count();
+            // the name as written, runs of spaces and tabs included
+            let source = lines.get(location.line - 1).copied().unwrap_or_default();
+            let code = match line_words(source) {
+                (_, Some((_, column))) => &source[..column - 1],
+                (_, None) => source,
+            };
+            let text = code.get(location.column - 1 + word.len()..).unwrap_or_default().trim();
+            let name = text.strip_prefix('"').and_then(|t| t.strip_suffix('"'))
+                .filter(|name| !name.is_empty())
+                .ok_or_else(|| error(".include needs a quoted file name".to_string()))?;
+
+            let path = self.find_include(name, &location.file)
+                .ok_or_else(|| error(format!("cannot find include file {}", name)))?;
+            let shown = path.display().to_string();
+            let canonical = fs::canonicalize(&path).map_err(|e| error(format!("cannot read {}: {}", shown, e)))?;
+            if stack.iter().any(|(p, _)| *p == canonical) {
+                let chain: Vec<&str> = stack.iter().map(|(_, s)| s.as_str()).collect();
+                return Err(error(format!("include cycle: {} -> {}", chain.join(" -> "), shown)));
+            }
+            let source = fs::read_to_string(&path).map_err(|e| error(format!("cannot read {}: {}", shown, e)))?;
+
+            let included = Self::words(&shown, source.lines(), *next_line, comments);
+            let included_lines: Vec<&str> = source.lines().collect();
+            *next_line += included_lines.len();
+            stack.push((canonical, shown));
+            output.extend(self.include_words(included, &included_lines, stack, next_line, comments)?);
+            stack.pop();
+
+            index = end;
+        }
+
+        Ok(output)
+    }
+
+    fn find_include(&self, name: &str, including_file: &str) -> Option<PathBuf> {
+        let directory = Path::new(including_file).parent().map(Path::to_path_buf).unwrap_or_default();
+        std::iter::once(directory)
+            .chain(self.include_paths.iter().cloned())
+            .map(|directory| directory.join(name))
+            .find(|path| path.is_file())
+    }
 
-                    if immediate == "B" {
-                        let token = Token::OutB;
-                        results.push(token)
+    // defines under the names the source uses
+    fn defined_constants(&self) -> HashMap<String, i64> {
+        self.defines.iter()
+            .map(|(name, value)| match self.dialect {
+                Dialect::Book => (Self::book_word(name), *value),
+                Dialect::Native => (name.clone(), *value),
+            })
+            .collect()
+    }
+
+    // keep only the words of taken .if / .ifdef / .ifndef ... .else ... .endif
+    // branches. Conditions see the defines and the constants defined above
+    // them. Macro bodies are left for the pass after expansion when
+    // skip_macros is set.
+    fn select_conditionals(&mut self, skip_macros: bool) -> Result<(), EmulatorErr> {
+        let mut constants = self.defined_constants();
+        // index of the directive, enclosing code active, a branch taken, .else seen
+        let mut conditions: Vec<(usize, bool, bool, bool)> = Vec::new();
+        let mut kept = Vec::new();
+        let mut in_macro = false;
+
+        let mut index = 0;
+        while index < self.opcodes.len() {
+            let active = conditions.iter().all(|&(_, outer, taken, _)| outer && taken);
+            let end = self.line_end(index);
+            let word = self.opcodes[index].as_str();
+
+            if skip_macros && (in_macro || word == ".macro") {
+                in_macro = word != ".endm";
+                if active {
+                    kept.push(index);
+                }
+                index += 1;
+                continue;
+            }
+
+            match word {
+                ".if" | ".ifdef" | ".ifndef" => {
+                    let taken = if active {
+                        let (text, _) = self.rest_of_line(index + 1);
+                        if word == ".if" {
+                            if text.is_empty() {
+                                return Err(self.error_at(index, ".if needs a condition"));
+                            }
+                            let symbol = |name: &str| constants.get(name).copied();
+                            expression::evaluate(&text, &symbol)
+                                .map_err(|e| self.error_at(index + 1, e.msg()))? != 0
+                        } else {
+                            if end != index + 2 {
+                                return Err(self.error_at(index, &format!("{} needs a name", word)));
+                            }
+                            constants.contains_key(&text) == (word == ".ifdef")
+                        }
                     } else {
-                        let token = Token::OutIm(self.binary_to_decimal(immediate).unwrap());
-                        results.push(token)
+                        false
+                    };
+                    conditions.push((index, active, taken, false));
+                    index = end;
+                },
+                ".else" => {
+                    match conditions.last_mut() {
+                        Some((_, _, _, true)) => return Err(self.error_at(index, "duplicate .else")),
+                        Some((_, _, taken, else_seen)) => {
+                            *taken = !*taken;
+                            *else_seen = true;
+                        },
+                        None => return Err(self.error_at(index, ".else without .if")),
+                    }
+                    index = end;
+                },
+                ".endif" => {
+                    if conditions.pop().is_none() {
+                        return Err(self.error_at(index, ".endif without .if"));
                     }
+                    index = end;
                 },
                 _ => {
-                    panic!("invalid instruction")
+                    if active {
+                        // constants as far as they are known yet, define_constants checks them
+                        let name_index = match word {
+                            ".equ" => Some(index + 1),
+                            _ if self.opcodes.get(index + 1).is_some_and(|w| w == "=") => Some(index),
+                            _ => None,
+                        };
+                        if let Some(name_index) = name_index.filter(|i| *i < end) {
+                            let (text, _) = self.rest_of_line(index + 2);
+                            let symbol = |name: &str| constants.get(name).copied();
+                            if let Ok(value) = expression::evaluate(&text, &symbol) {
+                                constants.insert(self.opcodes[name_index].clone(), value);
+                            }
+                        }
+                        kept.push(index);
+                    }
+                    index += 1;
                 },
             }
+        }
 
-            self.index += 1;
+        if let Some((index, _, _, _)) = conditions.last() {
+            return Err(self.error_at(*index, &format!("missing .endif for {}", self.opcodes[*index])));
         }
-        Ok(results)
+
+        self.opcodes = kept.iter().map(|i| self.opcodes[*i].clone()).collect();
+        self.positions = kept.iter().map(|i| self.positions[*i].clone()).collect();
+        self.lines = kept.iter().map(|i| self.lines[*i]).collect();
+        Ok(())
     }
 
-    fn binary_to_decimal(&self, text: &String) -> Result<u8, EmulatorErr> {
-        let s = text.as_str();
-        let decimal = u8::from_str_radix(s, 2);     // radix: cardinal number
+    // take out .macro name params ... .endm definitions and replace every
+    // call, a macro name starting a line, with the body of the macro
+    fn expand_macros(&mut self) -> Result<(), EmulatorErr> {
+        self.expansions.clear();
+        let mut macros = HashMap::new();
+        let mut words: Vec<Word> = Vec::new();
+
+        let mut index = 0;
+        while index < self.opcodes.len() {
+            match self.opcodes[index].as_str() {
+                ".macro" => {
+                    let end = self.line_end(index);
+                    let name = match self.opcodes.get(index + 1) {
+                        Some(name) if index + 1 < end => name.clone(),
+                        _ => return Err(self.error_at(index, ".macro needs a name")),
+                    };
+                    if !self.is_label(&name) {
+                        return Err(self.error_at(index + 1, &format!("invalid macro name {}", name)));
+                    }
+                    if self.isa.instructions().iter().any(|def| def.mnemonic == name) {
+                        return Err(self.error_at(index + 1, &format!("macro name {} is an instruction", name)));
+                    }
+                    if macros.contains_key(&name) {
+                        return Err(self.error_at(index + 1, &format!("duplicate macro {}", name)));
+                    }
+
+                    let mut body = Vec::new();
+                    let mut word = end;
+                    loop {
+                        match self.opcodes.get(word).map(|w| w.as_str()) {
+                            Some(".endm") => break,
+                            Some(".macro") => {
+                                return Err(self.error_at(word, &format!("nested .macro in macro {}", name)));
+                            },
+                            Some(text) => body.push((text.to_string(), self.lines[word])),
+                            None => return Err(self.error_at(index, &format!("missing .endm for macro {}", name))),
+                        }
+                        word += 1;
+                    }
+
+                    let params = self.opcodes[index + 2..end].to_vec();
+                    macros.insert(name.clone(), Macro::new(&name, params, body));
+                    index = word + 1;
+                },
+                ".endm" => return Err(self.error_at(index, ".endm without .macro")),
+                word => {
+                    words.push((word.to_string(), self.positions[index].clone(), self.lines[index]));
+                    index += 1;
+                },
+            }
+        }
 
-        decimal
-            .map_err(|_| EmulatorErr::new("From binary to decimal failed"))
+        let mut next_line = self.lines.iter().max().map_or(0, |line| line + 1);
+        let words = self.expand_words(words, &macros, 0, &mut next_line)?;
+        self.opcodes = words.iter().map(|(word, _, _)| word.clone()).collect();
+        self.positions = words.iter().map(|(_, location, _)| location.clone()).collect();
+        self.lines = words.iter().map(|(_, _, line)| *line).collect();
+        Ok(())
     }
-}
 
+    fn expand_words(
+        &mut self,
+        words: Vec<Word>,
+        macros: &HashMap<String, Macro>,
+        depth: usize,
+        next_line: &mut usize,
+    ) -> Result<Vec<Word>, EmulatorErr> {
+        let mut output = Vec::new();
 
+        let mut index = 0;
+        while index < words.len() {
+            let (word, location, line) = &words[index];
+            // first on its line, or after a label that is
+            let starts_line = match index {
+                0 => true,
+                _ if words[index - 1].2 != *line => true,
+                _ => words[index - 1].0.ends_with(':') && (index == 1 || words[index - 2].2 != *line),
+            };
 
-#[cfg(test)]
-mod parser_tests {
-    use crate::emulator::parser::Parser;
+            let definition = match macros.get(word) {
+                Some(definition) if starts_line => definition,
+                _ => {
+                    output.push(words[index].clone());
+                    index += 1;
+                    continue;
+                },
+            };
 
-    #[test]
-    fn parse_test() {
-        let instructions = vec![
-            "mov A 0001".to_string(),
-            "add A 0001".to_string(),
-        ];
+            let error = |msg: String| EmulatorErr::at(location, &msg);
+            if depth == MACRO_DEPTH {
+                return Err(error(format!("macro {} nests deeper than {} expansions", word, MACRO_DEPTH)));
+            }
+            let end = index + words[index..].iter().take_while(|w| w.2 == *line).count();
+            let args: Vec<String> = words[index + 1..end].iter().map(|w| w.0.clone()).collect();
+            if args.len() != definition.params.len() {
+                return Err(error(format!("macro {} takes {} arguments", word, definition.params.len())));
+            }
 
-        let mut code = Parser::new(instructions);
-        let result = code.parse().unwrap();
+            self.expansions.push(Expansion {
+                name: word.clone(),
+                text: words[index..end].iter().map(|w| w.0.as_str()).collect::<Vec<_>>().join(" "),
+                location: location.clone(),
+            });
 
-        assert_eq!(result.len(), 2);
+            // every body line gets a logical line of its own, all located at the call
+            let mut body_lines = HashMap::new();
+            let body = definition.instantiate(&args, self.expansions.len())
+                .into_iter()
+                .map(|(text, body_line)| {
+                    let line = *body_lines.entry(body_line).or_insert_with(|| {
+                        *next_line += 1;
+                        *next_line
+                    });
+                    (text, location.clone(), line)
+                })
+                .collect();
+            output.extend(self.expand_words(body, macros, depth + 1, next_line)?);
+
+            index = end;
+        }
+
+        Ok(output)
+    }
+
+    // .equ NAME value and NAME = value, collected ahead of the first pass so
+    // code can use a constant above its definition; a value may use the
+    // constants defined before it
+    fn define_constants(&mut self) -> Result<(), EmulatorErr> {
+        self.constants = self.defined_constants();
+        let mut constants = Vec::new();
+
+        let mut index = self.index;
+        while index < self.opcodes.len() {
+            let name_index = if self.opcodes[index] == ".equ" {
+                index + 1
+            } else if self.opcodes.get(index + 1).is_some_and(|w| w == "=") {
+                index
+            } else {
+                index += 1;
+                continue;
+            };
+            let value_index = index + 2;
+
+            let (text, end) = self.rest_of_line(value_index);
+            let name = match self.opcodes.get(name_index) {
+                Some(name) if !text.is_empty() && name_index < end => name.clone(),
+                _ => return Err(self.error_at(index, "constant needs a name and a value")),
+            };
+
+            if !self.is_label(&name) {
+                return Err(self.error_at(name_index, &format!("invalid constant name {}", name)));
+            }
+            if self.constants.contains_key(&name) {
+                return Err(self.error_at(name_index, &format!("duplicate constant {}", name)));
+            }
+            if self.opcodes.iter().any(|w| w.strip_suffix(':') == Some(name.as_str())) {
+                return Err(self.error_at(name_index, &format!("constant {} is already defined as a label", name)));
+            }
+
+            let value = self.constant_expression(&text, value_index)?;
+            self.constants.insert(name.clone(), value);
+            constants.push(Symbol {
+                name,
+                kind: SymbolKind::Constant,
+                value,
+                location: self.positions[index].clone(),
+            });
+
+            index = end;
+        }
+
+        self.symbols.extend(constants);
+        Ok(())
+    }
+
+    // bank section of every label, so forward jumps know if they cross banks
+    fn label_banks(&self) -> HashMap<String, u8> {
+        let mut label_banks = HashMap::new();
+        let mut bank = 0;
+
+        for (index, word) in self.opcodes.iter().enumerate() {
+            if word == ".bank" {
+                let (text, _) = self.rest_of_line(index + 1);
+                if let Some(Ok(b)) = self.constant_expression(&text, index + 1).ok().map(u8::try_from) {
+                    bank = b;
+                }
+            } else if word == ".org" {
+                let (text, _) = self.rest_of_line(index + 1);
+                if let Some(Ok(b)) = self.constant_expression(&text, index + 1).ok().map(|a| u8::try_from(a / BANK_SIZE as i64)) {
+                    bank = b;
+                }
+            } else if let Some(name) = word.strip_suffix(':') {
+                label_banks.entry(name.to_string()).or_insert(bank);
+            }
+        }
+
+        label_banks
+    }
+
+    // evaluate the expressions that name labels: jumps take the offset of the
+    // target inside the bank they run in, other operands the value as is
+    fn resolve_labels(
+        &mut self,
+        results: &mut [Token],
+        labels: &HashMap<String, usize>,
+        fixups: Vec<Fixup<I>>,
+    ) -> Result<Vec<usize>, EmulatorErr> {
+        let addresses = Self::addresses(results);
+
+        for (token_index, def, text, word_index, bank) in fixups {
+            let symbol = |name: &str| {
+                self.constants.get(name).copied()
+                    .or_else(|| labels.get(name).map(|index| addresses[*index] as i64))
+            };
+            let value = expression::evaluate(&text, &symbol)
+                .map_err(|e| self.error_at(word_index, e.msg()))?;
+            let operand = def.value_operand().unwrap_or(Operand::Immediate);
+
+            let value = if operand == Operand::Jump {
+                if value.div_euclid(BANK_SIZE as i64) != bank as i64 {
+                    return Err(self.error_at(word_index, &format!(
+                        "{} at address {} is outside bank {}",
+                        text, value, bank
+                    )));
+                }
+                value.rem_euclid(BANK_SIZE as i64)
+            } else {
+                value
+            };
+
+            results[token_index] = (def.token)(self.field(operand, value, word_index)?);
+        }
+
+        Ok(addresses)
+    }
+
+    // a label operand rather than a constant or a number
+    fn is_label_operand(&self, text: &str) -> bool {
+        self.is_label(text) && !self.constants.contains_key(text)
+    }
+
+    fn is_label(&self, text: &str) -> bool {
+        let mut chars = text.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                text != "A" && text != "B" && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            },
+            _ => false,
+        }
+    }
+
+    // index of the first word after the source line of the word at index
+    fn line_end(&self, index: usize) -> usize {
+        let line = match self.lines.get(index) {
+            Some(line) => *line,
+            None => return index,
+        };
+        index + self.lines[index..].iter()
+            .take_while(|l| **l == line)
+            .count()
+    }
+
+    // words from index to the end of the line of the word before it, and the end
+    fn rest_of_line(&self, index: usize) -> (String, usize) {
+        if index == 0 || index >= self.opcodes.len() {
+            return (String::new(), index);
+        }
+        let end = self.line_end(index - 1);
+        (self.opcodes[index..end].join(" "), end.max(index))
+    }
+
+    // table entry for a mnemonic, the text of each operand and the index after
+    // them; a value operand written last takes the rest of the line
+    fn instruction(&self, mnemonic: &str) -> Result<(&'static InstructionDef<I>, Vec<String>, usize), EmulatorErr> {
+        let defs: Vec<&'static InstructionDef<I>> = self.isa.instructions().iter()
+            .filter(|def| def.mnemonic == mnemonic)
+            .collect();
+        let count = match defs.first() {
+            Some(def) => def.operands.len(),
+            None => return Err(self.error("invalid instruction")),
+        };
+
+        let start = self.index + 1;
+        let end = self.line_end(self.index);
+        let words = &self.opcodes[start..end];
+        if words.len() < count {
+            return Err(self.error(&format!("{} needs {} operands", mnemonic, count)));
+        }
+
+        for def in defs {
+            let mut operands: Vec<String> = words[..count].to_vec();
+            let mut next = start + count;
+            if def.operands.last().is_some_and(|o| !o.keyword()) {
+                operands[count - 1] = words[count - 1..].join(" ");
+                next = end;
+            }
+
+            if def.operands.iter().zip(&operands).all(|(o, text)| self.accepts(o, text)) {
+                return Ok((def, operands, next));
+            }
+        }
+
+        Err(self.error(&format!("invalid operands for {}: {}", mnemonic, words.join(" "))))
+    }
+
+    // whether text has the shape of an operand; values are checked once the entry is known
+    fn accepts(&self, operand: &Operand, text: &str) -> bool {
+        let bracketed = text.starts_with('[') && text.ends_with(']');
+        match operand {
+            Operand::Keyword(keyword) => text == *keyword,
+            Operand::Memory => bracketed && text != "[B]",
+            Operand::Immediate | Operand::Jump | Operand::Address => !bracketed && text != "A" && text != "B",
+        }
     }
-}
\ No newline at end of file
+
+    // comma separated 8 bit values from index to the end of the line
+    fn list(&self, index: usize) -> Result<Vec<u8>, EmulatorErr> {
+        let (text, _) = self.rest_of_line(index);
+        if text.is_empty() {
+            return Ok(Vec::new());
+        }
+        text.split(',')
+            .map(|part| {
+                let value = self.constant_expression(part, index)?;
+                self.field(Operand::Address, value, index)
+            })
+            .collect()
+    }
+
+    // value of an expression over numbers and constants, errors at the word at index
+    fn constant_expression(&self, text: &str, index: usize) -> Result<i64, EmulatorErr> {
+        let symbol = |name: &str| self.constants.get(name).copied();
+        expression::evaluate(text, &symbol)
+            .map_err(|e| self.error_at(index, e.msg()))
+    }
+
+    // value checked against the width of the operand field: 4 bits for
+    // immediates and jumps, 8 bits for addresses
+    fn field(&self, operand: Operand, value: i64, index: usize) -> Result<u8, EmulatorErr> {
+        let bits = match operand {
+            Operand::Address | Operand::Memory => 8,
+            _ => 4,
+        };
+        if !(0..1 << bits).contains(&value) {
+            return Err(self.error_at(index, &format!("value {} does not fit a {} bit operand", value, bits)));
+        }
+        Ok(value as u8)
+    }
+
+    // the book writes the destination register first, the table the source
+    fn swap_registers(&mut self, index: usize) {
+        let register = |word: Option<&String>| matches!(word.map(|w| w.as_str()), Some("A") | Some("B"));
+        if register(self.opcodes.get(index + 1)) && register(self.opcodes.get(index + 2)) {
+            self.opcodes.swap(index + 1, index + 2);
+            self.positions.swap(index + 1, index + 2);
+        }
+    }
+
+    // registers in uppercase, everything else in lowercase
+    fn book_word(word: &str) -> String {
+        match word.to_lowercase().as_str() {
+            "a" => "A".to_string(),
+            "b" => "B".to_string(),
+            "[b]" => "[B]".to_string(),
+            word => word.to_string(),
+        }
+    }
+
+    // error about the current source word
+    fn error(&self, msg: &str) -> EmulatorErr {
+        self.error_at(self.index, msg)
+    }
+
+    // error about the source word at index
+    fn error_at(&self, index: usize, msg: &str) -> EmulatorErr {
+        match self.positions.get(index) {
+            Some(location) => EmulatorErr::at(location, msg),
+            None => EmulatorErr::new(msg),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod parser_tests {
+    use crate::emulator::compiler::Compiler;
+    use crate::emulator::instructions::{Address, AluOp, Token};
+    use crate::emulator::parser::{Dialect, Parser, Symbol, SymbolKind, Warning};
+    use crate::emulator::register::RegisterOp;
+    use crate::emulator::source_map::SourceLocation;
+
+    #[test]
+    fn parse_test() {
+        let instructions = vec![
+            "mov A 0001".to_string(),
+            "add A 0001".to_string(),
+        ];
+
+        let mut code = Parser::new(instructions);
+        let result = code.parse().unwrap();
+
+        assert_eq!(result.len(), 2);
+
+        // a second parse reads the source again from the start
+        let again = code.parse().unwrap();
+        assert_eq!(again.len(), 2);
+        assert!(matches!(again[1], Token::Add(RegisterOp::A, 0b0001)));
+    }
+
+    #[test]
+    fn parse_locations_test() {
+        let instructions = vec![
+            "mov A 0001".to_string(),
+            "".to_string(),
+            "  out B".to_string(),
+        ];
+
+        let mut code = Parser::with_file_name("test.sasm", instructions);
+        code.parse().unwrap();
+
+        assert_eq!(code.locations(), &[
+            SourceLocation::new("test.sasm", 1, 1),
+            SourceLocation::new("test.sasm", 3, 3),
+        ]);
+    }
+
+    #[test]
+    fn parse_error_location_test() {
+        let instructions = vec![
+            "mov A 0001".to_string(),
+            "add A 0002".to_string(),
+        ];
+
+        let mut code = Parser::with_file_name("test.sasm", instructions);
+        let err = code.parse().unwrap_err();
+
+        assert_eq!(err.msg(), "test.sasm:2:7: From binary to decimal failed");
+    }
+
+    #[test]
+    fn parse_ld_st_test() {
+        let instructions = vec![
+            "ld A [00010001]".to_string(),
+            "st A [B]".to_string(),
+        ];
+
+        let mut code = Parser::new(instructions);
+        let result = code.parse().unwrap();
+
+        assert!(matches!(result[0], Token::Ld(Address::Direct(0b00010001))));
+        assert!(matches!(result[1], Token::St(Address::IndexB)));
+    }
+
+    #[test]
+    fn parse_ld_st_error_test() {
+        let mut code = Parser::new(vec!["ld B [0001]".to_string()]);
+        assert!(code.parse().is_err());
+
+        let mut code = Parser::new(vec!["st A 0001".to_string()]);
+        assert!(code.parse().is_err());
+    }
+
+    #[test]
+    fn parse_label_test() {
+        let instructions = vec![
+            "mov A 0001".to_string(),
+            "loop: add A 0001".to_string(),
+            "jnc loop".to_string(),
+            "jmp end".to_string(),
+            "end:".to_string(),
+        ];
+
+        let mut code = Parser::new(instructions);
+        let result = code.parse().unwrap();
+
+        assert_eq!(result.len(), 4);
+        assert!(matches!(result[2], Token::Jnc(0b0001)));
+        assert!(matches!(result[3], Token::Jmp(0b0100)));
+    }
+
+    #[test]
+    fn parse_label_error_test() {
+        let mut code = Parser::new(vec!["jmp nowhere".to_string()]);
+        let err = code.parse().unwrap_err();
+        assert_eq!(err.msg(), "1:5: undefined label nowhere");
+        assert_eq!(err.location(), Some(&SourceLocation::new("", 1, 5)));
+        assert_eq!(err.text(), "undefined label nowhere");
+
+        let mut code = Parser::new(vec!["a: out B".to_string(), "a: out B".to_string()]);
+        assert!(code.parse().is_err());
+    }
+
+    #[test]
+    fn parse_cross_bank_test() {
+        let instructions = vec![
+            "start: jmp far".to_string(),
+            "jnc far".to_string(),
+            ".bank 0001".to_string(),
+            "out B".to_string(),
+            "far: jmp start".to_string(),
+        ];
+
+        let mut code = Parser::new(instructions);
+        let result = code.parse().unwrap();
+
+        assert!(matches!(result[0], Token::Bank(0b0001)));
+        assert!(matches!(result[1], Token::Jmp(0b0001)));
+        assert!(matches!(result[2], Token::Bank(0b0001)));
+        assert!(matches!(result[3], Token::Jnc(0b0001)));
+        assert!(matches!(result[4], Token::Bank(0b0000)));
+        assert!(matches!(result[5], Token::Org(16)));
+        assert!(matches!(result[6], Token::OutB));
+        assert!(matches!(result[7], Token::Bank(0b0000)));
+        assert!(matches!(result[8], Token::Jmp(0b0000)));
+        assert_eq!(code.locations()[4].line, 2);
+    }
+
+    #[test]
+    fn parse_bank_overflow_test() {
+        let mut instructions = vec!["jmp end".to_string()];
+        instructions.extend(vec!["out B".to_string(); 16]);
+        instructions.push("end: out B".to_string());
+
+        let mut code = Parser::new(instructions);
+        assert!(code.parse().is_err());
+    }
+
+    #[test]
+    fn parse_jz_jnz_test() {
+        let instructions = vec![
+            "top: jz 0011".to_string(),
+            "jnz top".to_string(),
+        ];
+
+        let mut code = Parser::new(instructions);
+        let result = code.parse().unwrap();
+
+        assert!(matches!(result[0], Token::Jz(0b0011)));
+        assert!(matches!(result[1], Token::Jnz(0b0000)));
+    }
+
+    #[test]
+    fn parse_sub_cmp_test() {
+        let instructions = vec![
+            "sub A 0001".to_string(),
+            "cmp B 0010".to_string(),
+        ];
+
+        let mut code = Parser::new(instructions);
+        let result = code.parse().unwrap();
+
+        assert!(matches!(result[0], Token::Sub(RegisterOp::A, 0b0001)));
+        assert!(matches!(result[1], Token::Cmp(RegisterOp::B, 0b0010)));
+    }
+
+    #[test]
+    fn parse_alu_test() {
+        let instructions = vec![
+            "add A B".to_string(),
+            "xor B A".to_string(),
+            "rcl A".to_string(),
+        ];
+
+        let mut code = Parser::new(instructions);
+        let result = code.parse().unwrap();
+
+        assert!(matches!(result[0], Token::Alu(AluOp::Add, RegisterOp::A)));
+        assert!(matches!(result[1], Token::Alu(AluOp::Xor, RegisterOp::B)));
+        assert!(matches!(result[2], Token::Alu(AluOp::Rcl, RegisterOp::A)));
+
+        let mut code = Parser::new(vec!["and A A".to_string()]);
+        assert!(code.parse().is_err());
+    }
+
+    #[test]
+    fn parse_call_ret_test() {
+        let instructions = vec![
+            "call 00000011".to_string(),
+            "call sub".to_string(),
+            ".bank 0001".to_string(),
+            "sub: ret".to_string(),
+        ];
+
+        let mut code = Parser::new(instructions);
+        let result = code.parse().unwrap();
+
+        assert!(matches!(result[0], Token::Call(0b00000011)));
+        assert!(matches!(result[1], Token::Call(16)));
+        assert!(matches!(result[3], Token::Ret));
+    }
+
+    #[test]
+    fn parse_hlt_nop_test() {
+        let instructions = vec![
+            "nop".to_string(),
+            "end: hlt".to_string(),
+            "jmp end".to_string(),
+        ];
+
+        let mut code = Parser::new(instructions);
+        let result = code.parse().unwrap();
+
+        assert!(matches!(result[0], Token::Nop));
+        assert!(matches!(result[1], Token::Hlt));
+        assert!(matches!(result[2], Token::Jmp(1)));
+    }
+
+    #[test]
+    fn parse_interrupt_test() {
+        let instructions = vec![
+            "ei".to_string(),
+            "di".to_string(),
+            "reti".to_string(),
+        ];
+
+        let mut code = Parser::new(instructions);
+        let result = code.parse().unwrap();
+
+        assert!(matches!(result[0], Token::Ei));
+        assert!(matches!(result[1], Token::Di));
+        assert!(matches!(result[2], Token::Reti));
+    }
+
+    #[test]
+    fn parse_book_dialect_test() {
+        let instructions = vec![
+            "MOV A, 0011".to_string(),
+            "MOV B, A".to_string(),
+            "mov a,b".to_string(),
+            "ADD A, 0001".to_string(),
+            "IN B".to_string(),
+            "OUT 0111".to_string(),
+            "Loop: JNC LOOP".to_string(),
+        ];
+
+        let mut code = Parser::with_file_name("test.sasm", instructions);
+        code.set_dialect(Dialect::Book);
+        let result = code.parse().unwrap();
+
+        assert!(matches!(result[0], Token::Mov(RegisterOp::A, 0b0011)));
+        assert!(matches!(result[1], Token::MovBA));
+        assert!(matches!(result[2], Token::MovAB));
+        assert!(matches!(result[3], Token::Add(RegisterOp::A, 0b0001)));
+        assert!(matches!(result[4], Token::In(RegisterOp::B)));
+        assert!(matches!(result[5], Token::OutIm(0b0111)));
+        assert!(matches!(result[6], Token::Jnc(0b0110)));
+        assert!(code.warnings().is_empty());
+        assert_eq!(code.positions[8], SourceLocation::new("test.sasm", 3, 5));
+    }
+
+    #[test]
+    fn parse_dialect_warning_test() {
+        let instructions = vec![
+            "mov A 0001".to_string(),
+            "mov A B".to_string(),
+        ];
+
+        let mut code = Parser::with_file_name("test.sasm", instructions);
+        let result = code.parse().unwrap();
+
+        assert!(matches!(result[1], Token::MovBA));
+        assert_eq!(code.warnings(), &[Warning {
+            location: Some(SourceLocation::new("test.sasm", 2, 1)),
+            message: "mov A B copies A into B; the book's MOV A, B copies B into A".to_string(),
+        }]);
+
+        let mut code = Parser::new(vec!["MOV A, B".to_string()]);
+        assert!(code.parse().is_err());
+        assert!("book".parse::<Dialect>().is_ok());
+        assert!("intel".parse::<Dialect>().is_err());
+    }
+
+    #[test]
+    fn parse_constants_test() {
+        let instructions = vec![
+            ".equ LED 0101".to_string(),
+            "start: mov A LED".to_string(),
+            "out LED".to_string(),
+            "ld A [PORT]".to_string(),
+            "jnc TOP".to_string(),
+            "jmp start".to_string(),
+            "PORT = 00010000".to_string(),
+            "TOP = LED".to_string(),
+        ];
+
+        let mut code = Parser::with_file_name("test.sasm", instructions);
+        let result = code.parse().unwrap();
+
+        assert_eq!(result.len(), 5);
+        assert!(matches!(result[0], Token::Mov(RegisterOp::A, 0b0101)));
+        assert!(matches!(result[1], Token::OutIm(0b0101)));
+        assert!(matches!(result[2], Token::Ld(Address::Direct(0b00010000))));
+        assert!(matches!(result[3], Token::Jnc(0b0101)));
+        assert!(matches!(result[4], Token::Jmp(0b0000)));
+
+        assert_eq!(code.symbols()[0], Symbol {
+            name: "LED".to_string(),
+            kind: SymbolKind::Constant,
+            value: 0b0101,
+            location: SourceLocation::new("test.sasm", 1, 1),
+        });
+        assert_eq!(code.symbols()[2].to_string(), "TOP constant 0101");
+        assert_eq!(code.symbols()[3].to_string(), "start label 0000");
+        assert_eq!(code.symbols().len(), 4);
+    }
+
+    #[test]
+    fn parse_constants_error_test() {
+        let cases = [
+            (vec![".equ LED 0101", "LED = 0110"], "test.sasm:2:1: duplicate constant LED"),
+            (vec![".equ LED 0101", "LED: out B"], "test.sasm:1:6: constant LED is already defined as a label"),
+            (vec!["A = 0001"], "test.sasm:1:1: invalid constant name A"),
+            (vec![".equ LED"], "test.sasm:1:1: constant needs a name and a value"),
+            (vec!["LED = LAMP", "LAMP = 0001"], "test.sasm:1:7: undefined symbol LAMP"),
+        ];
+
+        for (source, message) in cases {
+            let mut code = Parser::with_file_name("test.sasm", source.into_iter().map(String::from).collect());
+            assert_eq!(code.parse().unwrap_err().msg(), message);
+        }
+    }
+
+    #[test]
+    fn parse_expression_test() {
+        let instructions = vec![
+            "PATTERN = 0b0101".to_string(),
+            ".equ N 0".to_string(),
+            "loop: out PATTERN ^ 0b1111".to_string(),
+            "mov A (N-1)&0xF".to_string(),
+            "ld A [PORT + 1]".to_string(),
+            "jmp loop+1".to_string(),
+            "call far - 1".to_string(),
+            ".bank 0x4 >> 1".to_string(),
+            "far: out B".to_string(),
+            "PORT = 0x10".to_string(),
+        ];
+
+        let mut code = Parser::new(instructions);
+        let result = code.parse().unwrap();
+
+        assert!(matches!(result[0], Token::OutIm(0b1010)));
+        assert!(matches!(result[1], Token::Mov(RegisterOp::A, 0b1111)));
+        assert!(matches!(result[2], Token::Ld(Address::Direct(0b00010001))));
+        assert!(matches!(result[3], Token::Jmp(0b0001)));
+        assert!(matches!(result[4], Token::Call(0b00011111)));
+        assert!(matches!(result[5], Token::Org(32)));
+    }
+
+    #[test]
+    fn parse_expression_error_test() {
+        let cases = [
+            (vec!["mov A 1111 + 1"], "test.sasm:1:7: value 16 does not fit a 4 bit operand"),
+            (vec!["out 0 - 1"], "test.sasm:1:5: value -1 does not fit a 4 bit operand"),
+            (vec!["ld A [0x100]"], "test.sasm:1:6: value 256 does not fit a 8 bit operand"),
+            (vec!["mov A N"], "test.sasm:1:7: undefined symbol N"),
+            (vec!["top: out B", "jmp top + 10000"], "test.sasm:2:5: top + 10000 at address 16 is outside bank 0"),
+            (vec![".bank 10000"], "test.sasm:1:7: value 16 does not fit a 4 bit operand"),
+        ];
+
+        for (source, message) in cases {
+            let mut code = Parser::with_file_name("test.sasm", source.into_iter().map(String::from).collect());
+            assert_eq!(code.parse().unwrap_err().msg(), message);
+        }
+    }
+
+    #[test]
+    fn parse_macro_test() {
+        let instructions = vec![
+            ".macro wait REG COUNT".to_string(),
+            "loop: add REG 1111".to_string(),
+            "jnc loop".to_string(),
+            "out COUNT".to_string(),
+            ".endm".to_string(),
+            ".macro twice".to_string(),
+            "wait B 0001".to_string(),
+            ".endm".to_string(),
+            "start: wait A 0011".to_string(),
+            "twice".to_string(),
+            "jmp start".to_string(),
+        ];
+
+        let mut code = Parser::with_file_name("test.sasm", instructions);
+        let result = code.parse().unwrap();
+
+        assert_eq!(result.len(), 7);
+        assert!(matches!(result[0], Token::Add(RegisterOp::A, 0b1111)));
+        assert!(matches!(result[1], Token::Jnc(0b0000)));
+        assert!(matches!(result[2], Token::OutIm(0b0011)));
+        assert!(matches!(result[3], Token::Add(RegisterOp::B, 0b1111)));
+        assert!(matches!(result[4], Token::Jnc(0b0011)));
+        assert!(matches!(result[5], Token::OutIm(0b0001)));
+        assert!(matches!(result[6], Token::Jmp(0b0000)));
+
+        assert_eq!(code.locations()[0], SourceLocation::new("test.sasm", 9, 8));
+        assert_eq!(code.locations()[5], SourceLocation::new("test.sasm", 10, 1));
+        let calls: Vec<&str> = code.expansions().iter().map(|e| e.text.as_str()).collect();
+        assert_eq!(calls, vec!["wait A 0011", "twice", "wait B 0001"]);
+        assert_eq!(code.symbols()[1].to_string(), "loop__wait1 label 0000");
+        assert_eq!(code.symbols()[2].to_string(), "loop__wait3 label 0003");
+    }
+
+    #[test]
+    fn parse_macro_error_test() {
+        let cases = [
+            (vec![".macro wait", "out B"], "test.sasm:1:1: missing .endm for macro wait"),
+            (vec![".macro wait N", "out N", ".endm", "wait"], "test.sasm:4:1: macro wait takes 1 arguments"),
+            (vec![".macro loop", "loop", ".endm", "loop"], "test.sasm:4:1: macro loop nests deeper than 16 expansions"),
+            (vec!["out B", ".endm"], "test.sasm:2:1: .endm without .macro"),
+            (vec![".macro out", ".endm"], "test.sasm:1:8: macro name out is an instruction"),
+            (vec![".macro a", ".macro b", ".endm"], "test.sasm:2:1: nested .macro in macro a"),
+        ];
+
+        for (source, message) in cases {
+            let mut code = Parser::with_file_name("test.sasm", source.into_iter().map(String::from).collect());
+            assert_eq!(code.parse().unwrap_err().msg(), message);
+        }
+    }
+
+    // a temporary directory, removed again when the test is done with it
+    struct IncludeDir(std::path::PathBuf);
+
+    impl std::ops::Deref for IncludeDir {
+        type Target = std::path::Path;
+
+        fn deref(&self) -> &std::path::Path {
+            &self.0
+        }
+    }
+
+    impl Drop for IncludeDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // a fresh directory with the given files
+    fn include_dir(test: &str, files: &[(&str, &str)]) -> IncludeDir {
+        let dir = std::env::temp_dir().join(format!("sasm_{}_{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (name, text) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        IncludeDir(dir)
+    }
+
+    #[test]
+    fn parse_include_test() {
+        let dir = include_dir("include", &[
+            ("main.sasm", ".include \"leds.sasm\"\nblink\nout LED\n"),
+            ("leds.sasm", ".include \"common.sasm\"\n.macro blink\nout 0000\n.endm\n"),
+            ("lib/common.sasm", "LED = 0101\n"),
+            ("two  spaces\t.sasm", "out 0011\n"),
+        ]);
+        let main = dir.join("main.sasm");
+        let source = std::fs::read_to_string(&main).unwrap();
+
+        let mut code = Parser::with_file_name(main.to_str().unwrap(), source.lines().map(String::from).collect());
+        assert!(code.parse().is_err());
+
+        let mut code = Parser::with_file_name(main.to_str().unwrap(), source.lines().map(String::from).collect());
+        code.add_include_path(dir.join("lib").to_str().unwrap());
+        let result = code.parse().unwrap();
+
+        assert!(matches!(result[0], Token::OutIm(0b0000)));
+        assert!(matches!(result[1], Token::OutIm(0b0101)));
+        let led = &code.symbols()[0];
+        assert_eq!(led.location.file, dir.join("lib").join("common.sasm").display().to_string());
+        assert_eq!(led.location.line, 1);
+        assert_eq!(code.locations()[1].line, 3);
+
+        // the name is taken as written, the comment after it is not part of it
+        let source = vec![".include   \"two  spaces\t.sasm\" ; \"x\"".to_string()];
+        let mut code = Parser::with_file_name(main.to_str().unwrap(), source);
+        assert!(matches!(code.parse().unwrap()[0], Token::OutIm(0b0011)));
+    }
+
+    #[test]
+    fn parse_include_error_test() {
+        let dir = include_dir("include_error", &[
+            ("a.sasm", "out B\n.include \"b.sasm\"\n"),
+            ("b.sasm", "\n.include \"a.sasm\"\n"),
+            ("c.sasm", "out 0002\n"),
+        ]);
+        let shown = |name: &str| dir.join(name).display().to_string();
+        let parse = |name: &str, source: &str| {
+            let mut code = Parser::with_file_name(&shown(name), source.lines().map(String::from).collect());
+            code.parse().unwrap_err().msg().to_string()
+        };
+
+        assert_eq!(
+            parse("a.sasm", "out B\n.include \"b.sasm\""),
+            format!("{}:2:1: include cycle: {} -> {} -> {}", shown("b.sasm"), shown("a.sasm"), shown("b.sasm"), shown("a.sasm"))
+        );
+        assert_eq!(parse("main.sasm", ".include \"c.sasm\""), format!("{}:1:5: From binary to decimal failed", shown("c.sasm")));
+        assert_eq!(parse("main.sasm", ".include \"d.sasm\""), format!("{}:1:1: cannot find include file d.sasm", shown("main.sasm")));
+        assert_eq!(parse("main.sasm", ".include d.sasm"), format!("{}:1:1: .include needs a quoted file name", shown("main.sasm")));
+    }
+
+    #[test]
+    fn parse_conditional_test() {
+        let source = vec![
+            ".ifndef WORDS",
+            ".equ WORDS 10000",
+            ".endif",
+            ".macro light N",
+            ".if N > 1",
+            "out N",
+            ".else",
+            "out B",
+            ".endif",
+            ".endm",
+            ".ifdef EXTENDED",
+            "add A B",
+            ".if WORDS > 10000",
+            "call 00010000",
+            ".endif",
+            ".else",
+            "add A 0001",
+            ".endif",
+            "light 0",
+            "light 11",
+        ];
+        let parse = |defines: &[(&str, i64)]| {
+            let mut code = Parser::new(source.iter().map(|s| s.to_string()).collect());
+            for (name, value) in defines {
+                code.define(name, *value);
+            }
+            code.parse().unwrap()
+        };
+
+        let result = parse(&[]);
+        assert_eq!(result.len(), 3);
+        assert!(matches!(result[0], Token::Add(RegisterOp::A, 0b0001)));
+        assert!(matches!(result[1], Token::OutB));
+        assert!(matches!(result[2], Token::OutIm(0b0011)));
+
+        let result = parse(&[("EXTENDED", 1)]);
+        assert_eq!(result.len(), 3);
+        assert!(matches!(result[0], Token::Alu(AluOp::Add, RegisterOp::A)));
+
+        let result = parse(&[("EXTENDED", 1), ("WORDS", 256)]);
+        assert_eq!(result.len(), 4);
+        assert!(matches!(result[1], Token::Call(0b00010000)));
+    }
+
+    #[test]
+    fn parse_conditional_error_test() {
+        let cases = [
+            (vec![".if 1", "out B"], "test.sasm:1:1: missing .endif for .if"),
+            (vec![".if 1", ".else", ".else", ".endif"], "test.sasm:3:1: duplicate .else"),
+            (vec![".endif"], "test.sasm:1:1: .endif without .if"),
+            (vec![".else"], "test.sasm:1:1: .else without .if"),
+            (vec![".if"], "test.sasm:1:1: .if needs a condition"),
+            (vec![".if N", ".endif"], "test.sasm:1:5: undefined symbol N"),
+            (vec![".ifdef", ".endif"], "test.sasm:1:1: .ifdef needs a name"),
+        ];
+
+        for (source, message) in cases {
+            let mut code = Parser::with_file_name("test.sasm", source.into_iter().map(String::from).collect());
+            assert_eq!(code.parse().unwrap_err().msg(), message);
+        }
+    }
+
+    #[test]
+    fn parse_placement_test() {
+        let instructions = vec![
+            "out B",
+            ".align 100",
+            "data: .byte 0001, 0b1010,0xF",
+            ".fill 10",
+            ".fill 11, 0xAA",
+            ".org 0x10",
+            "jmp data",
+        ];
+
+        let mut code = Parser::with_file_name("test.sasm", instructions.into_iter().map(String::from).collect());
+        let tokens = code.parse().unwrap();
+        let (rom, _) = Compiler::new().compile_with_source_map(tokens, code.locations()).unwrap();
+
+        assert_eq!(rom, vec![
+            0b10010000, 0, 0, 0,
+            0b00000001, 0b00001010, 0b00001111, 0, 0,
+            0xAA, 0xAA, 0xAA, 0, 0, 0, 0,
+            0b10000100, 0b00000000, 0b11110100,
+        ]);
+    }
+
+    #[test]
+    fn parse_placement_error_test() {
+        let cases = [
+            (vec![".org 0 - 1"], "test.sasm:1:6: invalid .org -1"),
+            (vec![".align 0"], "test.sasm:1:8: invalid .align 0"),
+            (vec![".byte 1, 0x100"], "test.sasm:1:7: value 256 does not fit a 8 bit operand"),
+            (vec![".fill 1, 1, 1"], "test.sasm:1:1: .fill takes a count and a value"),
+            (vec![".byte"], "test.sasm:1:1: .byte needs a value"),
+            (vec![".fill", "out B"], "test.sasm:1:1: .fill needs a count"),
+            (vec![".org 0x7FFFFFFFFFFFFFFF"], "test.sasm:1:6: .org 9223372036854775807 is beyond the address space of 65536 words"),
+            (vec![".fill 0xFFFFFFFF, 0"], "test.sasm:1:7: .fill 4294967295 is beyond the address space of 65536 words"),
+            (vec![".org 0xFFFF", ".fill 0x7FFFFFFFFFFFFFFF", ".fill 0x7FFFFFFFFFFFFFFF"], "test.sasm:2:7: .fill 9223372036854775807 is beyond the address space of 65536 words"),
+            (vec![".org 1", "out B", ".org 0", ".byte 1, 1"], "test.sasm:4:1: address 1 is already used"),
+        ];
+
+        for (source, message) in cases {
+            let mut code = Parser::with_file_name("test.sasm", source.into_iter().map(String::from).collect());
+            let err = code.parse()
+                .and_then(|tokens| Compiler::new().compile_with_source_map(tokens, code.locations()))
+                .unwrap_err();
+            assert_eq!(err.msg(), message);
+        }
+    }
+
+    #[test]
+    fn parse_assert_test() {
+        let source = |assert: &str| vec![
+            ".assert end <= 10000, \"program too long\"".to_string(),
+            "start: out B".to_string(),
+            "jmp start".to_string(),
+            "end:".to_string(),
+            assert.to_string(),
+        ];
+
+        let mut code = Parser::with_file_name("test.sasm", source(".assert end == 10"));
+        assert_eq!(code.parse().unwrap().len(), 2);
+
+        let mut code = Parser::with_file_name("test.sasm", source(".assert end - start < 10, \"Needs One Word\""));
+        code.set_dialect(Dialect::Book);
+        assert_eq!(code.parse().unwrap_err().msg(), "test.sasm:5:1: assertion failed: Needs One Word");
+
+        let mut code = Parser::with_file_name("test.sasm", source(".assert end == 1"));
+        assert_eq!(code.parse().unwrap_err().msg(), "test.sasm:5:1: assertion failed: end == 1");
+
+        let mut code = Parser::with_file_name("test.sasm", source(".assert end == 1, no quotes\""));
+        assert_eq!(code.parse().unwrap_err().msg(), "test.sasm:5:1: .assert takes a condition and a quoted message");
+    }
+
+    #[test]
+    fn parse_comment_test() {
+        let source = vec![
+            "; blink the lamp".to_string(),
+            "start: out 0001 ; on".to_string(),
+            ".assert 1, \"a ; in quotes\" ; after".to_string(),
+            "jmp start;again".to_string(),
+        ];
+        let mut code = Parser::with_file_name("test.sasm", source);
+        let tokens = code.parse().unwrap();
+        assert!(matches!(tokens[..], [Token::OutIm(1), Token::Jmp(0)]));
+
+        let comments: Vec<(&str, usize, usize)> = code.comments().iter()
+            .map(|(text, location)| (text.as_str(), location.line, location.column))
+            .collect();
+        assert_eq!(comments, vec![("blink the lamp", 1, 1), ("on", 2, 17), ("after", 3, 28), ("again", 4, 10)]);
+        assert_eq!(code.comment(&code.locations()[1]), Some("again"));
+    }
+
+    #[test]
+    fn check_layout_test() {
+        let check = |source: Vec<&str>| {
+            let mut code = Parser::with_file_name("test.sasm", source.into_iter().map(String::from).collect());
+            let tokens = code.parse().unwrap();
+            code.check_layout(&tokens, 16, 4).map(|_| code.warnings().iter().map(|w| w.to_string()).collect::<Vec<_>>()).map_err(|e| e.msg().to_string())
+        };
+
+        assert_eq!(check(vec!["top: out B", "jnc top", "call top", "jmp top"]), Ok(vec![]));
+        assert_eq!(
+            check(vec!["out B"]),
+            Ok(vec!["test.sasm:1:1: execution runs past the last instruction out B into the fill words".to_string()])
+        );
+
+        let mut long = vec!["out B"; 16];
+        long.push("hlt");
+        assert_eq!(check(long), Err("test.sasm:17:1: address 16 is past the end of rom (16 words)".to_string()));
+        assert_eq!(
+            check(vec!["ld A [00000001]", "jmp 0001"]),
+            Err("test.sasm:2:1: jmp 0001 goes to address 1, which is not an instruction".to_string())
+        );
+        assert_eq!(
+            check(vec!["call data", "hlt", "data: .byte 1"]),
+            Err("test.sasm:1:1: call 00000011 goes to address 3, which is not an instruction".to_string())
+        );
+
+        // words .fill leaves free do not move the jump into another bank
+        let source = vec![".org 10000", "loop: out B", ".fill 10000", "jmp loop"];
+        let mut code = Parser::with_file_name("test.sasm", source.into_iter().map(String::from).collect());
+        let tokens = code.parse().unwrap();
+        assert!(matches!(tokens[3], Token::Jmp(0)));
+        assert_eq!(code.jump_targets(&tokens)[3], Some(0b10000));
+        assert!(code.check_layout(&tokens, 256, 8).is_ok());
+
+        // the 8 bit address word of call cannot reach a wider rom
+        let mut code = Parser::with_file_name("test.sasm", vec!["top: call top".to_string(), "hlt".to_string()]);
+        let tokens = code.parse().unwrap();
+        assert!(code.check_layout(&tokens, 256, 8).is_ok());
+        assert_eq!(
+            code.check_layout(&tokens, 256, 9).unwrap_err().msg(),
+            "test.sasm:1:6: call takes an 8 bit address, this machine has 9 bit addresses"
+        );
+    }
+}
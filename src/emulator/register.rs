@@ -6,10 +6,15 @@ pub struct Register {
     register_a: u8,
     register_b: u8,
     carry_flag: u8,
-    pc: u8,
+    zero_flag: u8,
+    pc: u16,
+    bank: u8,
+    stack: Vec<u16>,
+    interrupt_enable: u8,
+    interrupt_frame: Option<(u16, u8)>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RegisterOp {
     A,
     B,
@@ -29,16 +34,68 @@ impl Register {
         Self::default()
     }
 
-    pub fn set_pc(&mut self, value: u8) {
+    pub fn set_pc(&mut self, value: u16) {
         self.pc = value
     }
 
-    pub fn pc(&self) -> u8 {
+    pub fn pc(&self) -> u16 {
         self.pc
     }
 
     pub fn inc_pc(&mut self) {
-        self.pc += 1
+        self.pc = self.pc.wrapping_add(1)
+    }
+
+    // rom bank that jump immediates address
+    pub fn set_bank(&mut self, value: u8) {
+        self.bank = value
+    }
+
+    pub fn bank(&self) -> u8 {
+        self.bank
+    }
+
+    // return addresses, most recent call last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn push_stack(&mut self, value: u16) {
+        self.stack.push(value)
+    }
+
+    pub fn pop_stack(&mut self) -> Option<u16> {
+        self.stack.pop()
+    }
+
+    // forget the oldest return address when a wrapping stack is full
+    pub fn drop_stack_bottom(&mut self) {
+        if !self.stack.is_empty() {
+            self.stack.remove(0);
+        }
+    }
+
+    // 1 lets a pending interrupt request in
+    pub fn set_interrupt_enable(&mut self, value: u8) {
+        self.interrupt_enable = value
+    }
+
+    pub fn interrupt_enable(&self) -> u8 {
+        self.interrupt_enable
+    }
+
+    // pc and carry saved on interrupt entry, restored by reti
+    pub fn save_interrupt_frame(&mut self, pc: u16, carry: u8) {
+        self.interrupt_frame = Some((pc, carry))
+    }
+
+    pub fn take_interrupt_frame(&mut self) -> Option<(u16, u8)> {
+        self.interrupt_frame.take()
+    }
+
+    // true while a handler runs that has not returned with reti
+    pub fn in_interrupt(&self) -> bool {
+        self.interrupt_frame.is_some()
     }
 
     pub fn set_carry_flag(&mut self, value: u8) {
@@ -49,6 +106,14 @@ impl Register {
         self.carry_flag
     }
 
+    pub fn set_zero_flag(&mut self, value: u8) {
+        self.zero_flag = value
+    }
+
+    pub fn zero_flag(&self) -> u8 {
+        self.zero_flag
+    }
+
     pub fn set_register_a(&mut self, value: u8) {
         self.register_a = value
     }
@@ -84,6 +149,10 @@ mod register_tests {
         let carry_flag = register.carry_flag();
         assert_eq!(carry_flag, 1);
 
+        register.set_zero_flag(1);
+        let zero_flag = register.zero_flag();
+        assert_eq!(zero_flag, 1);
+
         register.set_register_a(1);
         let a = register.register_a();
         assert_eq!(a, 1);
@@ -91,6 +160,17 @@ mod register_tests {
         register.set_register_b(1);
         let b = register.register_b();
         assert_eq!(b, 1);
+
+        register.set_bank(2);
+        let bank = register.bank();
+        assert_eq!(bank, 2);
+
+        register.push_stack(3);
+        register.push_stack(5);
+        register.drop_stack_bottom();
+        assert_eq!(register.stack(), &[5]);
+        assert_eq!(register.pop_stack(), Some(5));
+        assert_eq!(register.pop_stack(), None);
     }
 
 }
\ No newline at end of file
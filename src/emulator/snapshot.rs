@@ -0,0 +1,51 @@
+use std::fmt;
+use crate::emulator::executor::HaltReason;
+
+// machine state at one point of the run, for traces and debuggers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub pc: u16,
+    pub bank: u8,
+    pub register_a: u8,
+    pub register_b: u8,
+    pub carry_flag: u8,
+    pub zero_flag: u8,
+    pub interrupt_enable: u8,
+    pub interrupt_pending: bool,
+    pub input: u8,
+    pub output: u8,
+    pub ram: Vec<u8>,
+    pub stack: Vec<u16>,
+    pub halted: Option<HaltReason>,
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pc: {} bank: {} A: {:04b} B: {:04b} carry: {} zero: {} ie: {} in: {:04b} out: {:04b}",
+            self.pc, self.bank, self.register_a, self.register_b, self.carry_flag, self.zero_flag,
+            self.interrupt_enable, self.input, self.output
+        )?;
+
+        if self.interrupt_pending {
+            write!(f, " interrupt pending")?;
+        }
+
+        if !self.ram.is_empty() {
+            let words: Vec<String> = self.ram.iter().map(|w| format!("{:x}", w)).collect();
+            write!(f, " ram: [{}]", words.join(" "))?;
+        }
+
+        if !self.stack.is_empty() {
+            let addresses: Vec<String> = self.stack.iter().map(|a| a.to_string()).collect();
+            write!(f, " stack: [{}]", addresses.join(" "))?;
+        }
+
+        if let Some(reason) = self.halted {
+            write!(f, " halted: {}", reason)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use std::{collections::BTreeMap, fmt, fs};
+use crate::EmulatorErr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SourceLocation {
+    pub fn new(file: &str, line: usize, column: usize) -> Self {
+        Self { file: file.to_string(), line, column }
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.file.is_empty() {
+            write!(f, "{}:{}", self.line, self.column)
+        } else {
+            write!(f, "{}:{}:{}", self.file, self.line, self.column)
+        }
+    }
+}
+
+// rom address -> position of the instruction in the .sasm source
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    entries: BTreeMap<usize, SourceLocation>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, address: usize, location: SourceLocation) {
+        self.entries.insert(address, location);
+    }
+
+    pub fn get(&self, address: usize) -> Option<&SourceLocation> {
+        self.entries.get(&address)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&usize, &SourceLocation)> {
+        self.entries.iter()
+    }
+
+    // sidecar format: one "address<TAB>line<TAB>column<TAB>file" record per line,
+    // a backslash, tab or newline in the file name is written as \\, \t or \n
+    pub fn to_sidecar(&self) -> String {
+        let mut text = String::new();
+        for (address, location) in &self.entries {
+            let file = location.file.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n");
+            text.push_str(&format!("{}\t{}\t{}\t{}\n", address, location.line, location.column, file));
+        }
+        text
+    }
+
+    pub fn write_sidecar(&self, path: &str) -> Result<(), EmulatorErr> {
+        fs::write(path, self.to_sidecar())
+            .map_err(|e| EmulatorErr::new(&format!("write source map {}: {}", path, e)))
+    }
+}
+
+
+#[cfg(test)]
+mod source_map_tests {
+    use crate::emulator::source_map::{SourceLocation, SourceMap};
+
+    #[test]
+    fn sidecar_test() {
+        let mut source_map = SourceMap::new();
+        source_map.insert(0, SourceLocation::new("instruction1.sasm", 1, 1));
+        source_map.insert(1, SourceLocation::new("dir with space/a.sasm", 2, 5));
+
+        let text = source_map.to_sidecar();
+        assert_eq!(text, "0\t1\t1\tinstruction1.sasm\n1\t2\t5\tdir with space/a.sasm\n");
+
+        // names with tabs or newlines keep to one record
+        source_map.insert(2, SourceLocation::new("tab\there\\new\nline.sasm", 3, 1));
+        assert_eq!(source_map.to_sidecar().lines().nth(2), Some("2\t3\t1\ttab\\there\\\\new\\nline.sasm"));
+    }
+}
@@ -0,0 +1,569 @@
+use crate::emulator::executor::HaltReason;
+use crate::emulator::instructions::{Address, AluOp, Token};
+use crate::emulator::isa::{Encoding, InstructionDef, Isa, Operand, RegisterLayout};
+use crate::emulator::register::RegisterOp;
+
+const TD4_REGISTERS: [RegisterLayout; 5] = [
+    RegisterLayout { name: "A", width: 4, read: |r| r.register_a() as u16 },
+    RegisterLayout { name: "B", width: 4, read: |r| r.register_b() as u16 },
+    RegisterLayout { name: "C", width: 1, read: |r| r.carry_flag() as u16 },
+    RegisterLayout { name: "Z", width: 1, read: |r| r.zero_flag() as u16 },
+    RegisterLayout { name: "IE", width: 1, read: |r| r.interrupt_enable() as u16 },
+];
+
+// opcode 1000 is a prefix: the low nibble picks an extended instruction
+const EXT: u8 = 0b1000;
+
+const NONE: &[Operand] = &[];
+const A: &[Operand] = &[Operand::Keyword("A")];
+const B: &[Operand] = &[Operand::Keyword("B")];
+const A_B: &[Operand] = &[Operand::Keyword("A"), Operand::Keyword("B")];
+const B_A: &[Operand] = &[Operand::Keyword("B"), Operand::Keyword("A")];
+const IM: &[Operand] = &[Operand::Immediate];
+const A_IM: &[Operand] = &[Operand::Keyword("A"), Operand::Immediate];
+const B_IM: &[Operand] = &[Operand::Keyword("B"), Operand::Immediate];
+const JUMP: &[Operand] = &[Operand::Jump];
+const ADDRESS: &[Operand] = &[Operand::Address];
+const A_MEMORY: &[Operand] = &[Operand::Keyword("A"), Operand::Memory];
+const A_INDEX_B: &[Operand] = &[Operand::Keyword("A"), Operand::Keyword("[B]")];
+
+// every TD4 instruction; the parser, compiler, executor and disassembler all read this.
+// "mov A B" is B <- A in this dialect, the source register comes first
+static TD4_INSTRUCTIONS: [InstructionDef<Td4>; 48] = [
+    InstructionDef {
+        mnemonic: "mov",
+        operands: A_IM,
+        description: "loads the immediate into A; clears carry",
+        encoding: Encoding::Opcode(0b0011),
+        token: |v| Token::Mov(RegisterOp::A, v),
+        value: |t| match t { Token::Mov(RegisterOp::A, v) => Some(*v), _ => None },
+        execute: |cpu, v| { cpu.mov_a(v); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "mov",
+        operands: B_IM,
+        description: "loads the immediate into B; clears carry",
+        encoding: Encoding::Opcode(0b0111),
+        token: |v| Token::Mov(RegisterOp::B, v),
+        value: |t| match t { Token::Mov(RegisterOp::B, v) => Some(*v), _ => None },
+        execute: |cpu, v| { cpu.mov_b(v); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "mov",
+        operands: A_B,
+        description: "copies A into B; clears carry",
+        encoding: Encoding::Opcode(0b0100),
+        token: |_| Token::MovBA,
+        value: |t| match t { Token::MovBA => Some(0), _ => None },
+        execute: |cpu, _| { cpu.mov_b2a(); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "mov",
+        operands: B_A,
+        description: "copies B into A; clears carry",
+        encoding: Encoding::Opcode(0b0001),
+        token: |_| Token::MovAB,
+        value: |t| match t { Token::MovAB => Some(0), _ => None },
+        execute: |cpu, _| { cpu.mov_a2b(); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "add",
+        operands: A_IM,
+        description: "adds the immediate to A; carry on overflow, zero on a 0 result",
+        encoding: Encoding::Opcode(0b0000),
+        token: |v| Token::Add(RegisterOp::A, v),
+        value: |t| match t { Token::Add(RegisterOp::A, v) => Some(*v), _ => None },
+        execute: |cpu, v| { cpu.add_a(v); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "add",
+        operands: B_IM,
+        description: "adds the immediate to B; carry on overflow, zero on a 0 result",
+        encoding: Encoding::Opcode(0b0101),
+        token: |v| Token::Add(RegisterOp::B, v),
+        value: |t| match t { Token::Add(RegisterOp::B, v) => Some(*v), _ => None },
+        execute: |cpu, v| { cpu.add_b(v); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "sub",
+        operands: A_IM,
+        description: "subtracts the immediate from A; carry holds the borrow, zero on a 0 result",
+        encoding: Encoding::Opcode(0b1010),
+        token: |v| Token::Sub(RegisterOp::A, v),
+        value: |t| match t { Token::Sub(RegisterOp::A, v) => Some(*v), _ => None },
+        execute: |cpu, v| { cpu.sub_a(v & 0x0f); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "sub",
+        operands: B_IM,
+        description: "subtracts the immediate from B; carry holds the borrow, zero on a 0 result",
+        encoding: Encoding::Prefix(EXT, 0b0101),
+        token: |v| Token::Sub(RegisterOp::B, v),
+        value: |t| match t { Token::Sub(RegisterOp::B, v) => Some(*v), _ => None },
+        execute: |cpu, v| { cpu.sub_b(v & 0x0f); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "cmp",
+        operands: A_IM,
+        description: "subtracts the immediate from A without keeping the result; carry holds the borrow, zero when equal",
+        encoding: Encoding::Prefix(EXT, 0b0110),
+        token: |v| Token::Cmp(RegisterOp::A, v),
+        value: |t| match t { Token::Cmp(RegisterOp::A, v) => Some(*v), _ => None },
+        execute: |cpu, v| { cpu.sub_flags(cpu.register.register_a(), v & 0x0f); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "cmp",
+        operands: B_IM,
+        description: "subtracts the immediate from B without keeping the result; carry holds the borrow, zero when equal",
+        encoding: Encoding::Prefix(EXT, 0b0111),
+        token: |v| Token::Cmp(RegisterOp::B, v),
+        value: |t| match t { Token::Cmp(RegisterOp::B, v) => Some(*v), _ => None },
+        execute: |cpu, v| { cpu.sub_flags(cpu.register.register_b(), v & 0x0f); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "add",
+        operands: A_B,
+        description: "adds B to A (--extended-alu); carry on overflow, zero on a 0 result",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0000_0000),
+        token: |_| Token::Alu(AluOp::Add, RegisterOp::A),
+        value: |t| match t { Token::Alu(AluOp::Add, RegisterOp::A) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Add, RegisterOp::A),
+    },
+    InstructionDef {
+        mnemonic: "add",
+        operands: B_A,
+        description: "adds A to B (--extended-alu); carry on overflow, zero on a 0 result",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0001_0000),
+        token: |_| Token::Alu(AluOp::Add, RegisterOp::B),
+        value: |t| match t { Token::Alu(AluOp::Add, RegisterOp::B) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Add, RegisterOp::B),
+    },
+    InstructionDef {
+        mnemonic: "and",
+        operands: A_B,
+        description: "ands B into A (--extended-alu); clears carry, zero on a 0 result",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0000_0001),
+        token: |_| Token::Alu(AluOp::And, RegisterOp::A),
+        value: |t| match t { Token::Alu(AluOp::And, RegisterOp::A) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::And, RegisterOp::A),
+    },
+    InstructionDef {
+        mnemonic: "and",
+        operands: B_A,
+        description: "ands A into B (--extended-alu); clears carry, zero on a 0 result",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0001_0001),
+        token: |_| Token::Alu(AluOp::And, RegisterOp::B),
+        value: |t| match t { Token::Alu(AluOp::And, RegisterOp::B) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::And, RegisterOp::B),
+    },
+    InstructionDef {
+        mnemonic: "or",
+        operands: A_B,
+        description: "ors B into A (--extended-alu); clears carry, zero on a 0 result",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0000_0010),
+        token: |_| Token::Alu(AluOp::Or, RegisterOp::A),
+        value: |t| match t { Token::Alu(AluOp::Or, RegisterOp::A) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Or, RegisterOp::A),
+    },
+    InstructionDef {
+        mnemonic: "or",
+        operands: B_A,
+        description: "ors A into B (--extended-alu); clears carry, zero on a 0 result",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0001_0010),
+        token: |_| Token::Alu(AluOp::Or, RegisterOp::B),
+        value: |t| match t { Token::Alu(AluOp::Or, RegisterOp::B) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Or, RegisterOp::B),
+    },
+    InstructionDef {
+        mnemonic: "xor",
+        operands: A_B,
+        description: "xors B into A (--extended-alu); clears carry, zero on a 0 result",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0000_0011),
+        token: |_| Token::Alu(AluOp::Xor, RegisterOp::A),
+        value: |t| match t { Token::Alu(AluOp::Xor, RegisterOp::A) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Xor, RegisterOp::A),
+    },
+    InstructionDef {
+        mnemonic: "xor",
+        operands: B_A,
+        description: "xors A into B (--extended-alu); clears carry, zero on a 0 result",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0001_0011),
+        token: |_| Token::Alu(AluOp::Xor, RegisterOp::B),
+        value: |t| match t { Token::Alu(AluOp::Xor, RegisterOp::B) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Xor, RegisterOp::B),
+    },
+    InstructionDef {
+        mnemonic: "not",
+        operands: A,
+        description: "inverts A (--extended-alu); clears carry, zero on a 0 result",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0000_0100),
+        token: |_| Token::Alu(AluOp::Not, RegisterOp::A),
+        value: |t| match t { Token::Alu(AluOp::Not, RegisterOp::A) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Not, RegisterOp::A),
+    },
+    InstructionDef {
+        mnemonic: "not",
+        operands: B,
+        description: "inverts B (--extended-alu); clears carry, zero on a 0 result",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0001_0100),
+        token: |_| Token::Alu(AluOp::Not, RegisterOp::B),
+        value: |t| match t { Token::Alu(AluOp::Not, RegisterOp::B) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Not, RegisterOp::B),
+    },
+    InstructionDef {
+        mnemonic: "shl",
+        operands: A,
+        description: "shifts A left, bit 3 goes to carry (--extended-alu)",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0000_0101),
+        token: |_| Token::Alu(AluOp::Shl, RegisterOp::A),
+        value: |t| match t { Token::Alu(AluOp::Shl, RegisterOp::A) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Shl, RegisterOp::A),
+    },
+    InstructionDef {
+        mnemonic: "shl",
+        operands: B,
+        description: "shifts B left, bit 3 goes to carry (--extended-alu)",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0001_0101),
+        token: |_| Token::Alu(AluOp::Shl, RegisterOp::B),
+        value: |t| match t { Token::Alu(AluOp::Shl, RegisterOp::B) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Shl, RegisterOp::B),
+    },
+    InstructionDef {
+        mnemonic: "shr",
+        operands: A,
+        description: "shifts A right, bit 0 goes to carry (--extended-alu)",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0000_0110),
+        token: |_| Token::Alu(AluOp::Shr, RegisterOp::A),
+        value: |t| match t { Token::Alu(AluOp::Shr, RegisterOp::A) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Shr, RegisterOp::A),
+    },
+    InstructionDef {
+        mnemonic: "shr",
+        operands: B,
+        description: "shifts B right, bit 0 goes to carry (--extended-alu)",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0001_0110),
+        token: |_| Token::Alu(AluOp::Shr, RegisterOp::B),
+        value: |t| match t { Token::Alu(AluOp::Shr, RegisterOp::B) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Shr, RegisterOp::B),
+    },
+    InstructionDef {
+        mnemonic: "rcl",
+        operands: A,
+        description: "rotates A left through carry (--extended-alu)",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0000_0111),
+        token: |_| Token::Alu(AluOp::Rcl, RegisterOp::A),
+        value: |t| match t { Token::Alu(AluOp::Rcl, RegisterOp::A) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Rcl, RegisterOp::A),
+    },
+    InstructionDef {
+        mnemonic: "rcl",
+        operands: B,
+        description: "rotates B left through carry (--extended-alu)",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0001_0111),
+        token: |_| Token::Alu(AluOp::Rcl, RegisterOp::B),
+        value: |t| match t { Token::Alu(AluOp::Rcl, RegisterOp::B) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Rcl, RegisterOp::B),
+    },
+    InstructionDef {
+        mnemonic: "rcr",
+        operands: A,
+        description: "rotates A right through carry (--extended-alu)",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0000_1000),
+        token: |_| Token::Alu(AluOp::Rcr, RegisterOp::A),
+        value: |t| match t { Token::Alu(AluOp::Rcr, RegisterOp::A) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Rcr, RegisterOp::A),
+    },
+    InstructionDef {
+        mnemonic: "rcr",
+        operands: B,
+        description: "rotates B right through carry (--extended-alu)",
+        encoding: Encoding::PrefixWord(EXT, 0b1000, 0b0001_1000),
+        token: |_| Token::Alu(AluOp::Rcr, RegisterOp::B),
+        value: |t| match t { Token::Alu(AluOp::Rcr, RegisterOp::B) => Some(0), _ => None },
+        execute: |cpu, _| cpu.alu(AluOp::Rcr, RegisterOp::B),
+    },
+    InstructionDef {
+        mnemonic: "jmp",
+        operands: JUMP,
+        description: "jumps within the current bank; clears carry",
+        encoding: Encoding::Opcode(0b1111),
+        token: Token::Jmp,
+        value: |t| match t { Token::Jmp(v) => Some(*v), _ => None },
+        execute: |cpu, v| { cpu.jmp(v); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "jnc",
+        operands: JUMP,
+        description: "jumps when carry is clear; clears carry",
+        encoding: Encoding::Opcode(0b1110),
+        token: Token::Jnc,
+        value: |t| match t { Token::Jnc(v) => Some(*v), _ => None },
+        execute: |cpu, v| { cpu.branch(cpu.register.carry_flag() == 0, v); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "jz",
+        operands: JUMP,
+        description: "jumps when zero is set; clears carry",
+        encoding: Encoding::Opcode(0b1100),
+        token: Token::Jz,
+        value: |t| match t { Token::Jz(v) => Some(*v), _ => None },
+        execute: |cpu, v| { cpu.branch(cpu.register.zero_flag() == 1, v); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "jnz",
+        operands: JUMP,
+        description: "jumps when zero is clear; clears carry",
+        encoding: Encoding::Opcode(0b1101),
+        token: Token::Jnz,
+        value: |t| match t { Token::Jnz(v) => Some(*v), _ => None },
+        execute: |cpu, v| { cpu.branch(cpu.register.zero_flag() == 0, v); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "in",
+        operands: A,
+        description: "reads the input port into A; clears carry",
+        encoding: Encoding::Opcode(0b0010),
+        token: |_| Token::In(RegisterOp::A),
+        value: |t| match t { Token::In(RegisterOp::A) => Some(0), _ => None },
+        execute: |cpu, _| { cpu.in_a(); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "in",
+        operands: B,
+        description: "reads the input port into B; clears carry",
+        encoding: Encoding::Opcode(0b0110),
+        token: |_| Token::In(RegisterOp::B),
+        value: |t| match t { Token::In(RegisterOp::B) => Some(0), _ => None },
+        execute: |cpu, _| { cpu.in_b(); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "out",
+        operands: B,
+        description: "writes B to the output port; clears carry",
+        encoding: Encoding::Opcode(0b1001),
+        token: |_| Token::OutB,
+        value: |t| match t { Token::OutB => Some(0), _ => None },
+        execute: |cpu, _| cpu.out_b(),
+    },
+    InstructionDef {
+        mnemonic: "out",
+        operands: IM,
+        description: "writes the immediate to the output port; clears carry",
+        encoding: Encoding::Opcode(0b1011),
+        token: Token::OutIm,
+        value: |t| match t { Token::OutIm(v) => Some(*v), _ => None },
+        execute: |cpu, v| cpu.out_im(v),
+    },
+    InstructionDef {
+        mnemonic: "ld",
+        operands: A_MEMORY,
+        description: "loads the ram word or mapped device at the address into A; clears carry",
+        encoding: Encoding::Prefix(EXT, 0b0000),
+        token: |v| Token::Ld(Address::Direct(v)),
+        value: |t| match t { Token::Ld(Address::Direct(v)) => Some(*v), _ => None },
+        execute: |cpu, v| cpu.ld_a(v as usize),
+    },
+    InstructionDef {
+        mnemonic: "st",
+        operands: A_MEMORY,
+        description: "stores A to the ram word or mapped device at the address; clears carry",
+        encoding: Encoding::Prefix(EXT, 0b0001),
+        token: |v| Token::St(Address::Direct(v)),
+        value: |t| match t { Token::St(Address::Direct(v)) => Some(*v), _ => None },
+        execute: |cpu, v| cpu.st_a(v as usize),
+    },
+    InstructionDef {
+        mnemonic: "ld",
+        operands: A_INDEX_B,
+        description: "loads the word at the address in B into A; clears carry",
+        encoding: Encoding::Prefix(EXT, 0b0010),
+        token: |_| Token::Ld(Address::IndexB),
+        value: |t| match t { Token::Ld(Address::IndexB) => Some(0), _ => None },
+        execute: |cpu, _| cpu.ld_a(cpu.register.register_b() as usize),
+    },
+    InstructionDef {
+        mnemonic: "st",
+        operands: A_INDEX_B,
+        description: "stores A at the address in B; clears carry",
+        encoding: Encoding::Prefix(EXT, 0b0011),
+        token: |_| Token::St(Address::IndexB),
+        value: |t| match t { Token::St(Address::IndexB) => Some(0), _ => None },
+        execute: |cpu, _| cpu.st_a(cpu.register.register_b() as usize),
+    },
+    InstructionDef {
+        mnemonic: "bank",
+        operands: IM,
+        description: "selects the rom bank jumps go to",
+        encoding: Encoding::Prefix(EXT, 0b0100),
+        token: Token::Bank,
+        value: |t| match t { Token::Bank(v) => Some(*v), _ => None },
+        execute: |cpu, v| cpu.select_bank(v),
+    },
+    InstructionDef {
+        mnemonic: "call",
+        operands: ADDRESS,
+        description: "pushes the return address and jumps; clears carry",
+        encoding: Encoding::Prefix(EXT, 0b1001),
+        token: Token::Call,
+        value: |t| match t { Token::Call(v) => Some(*v), _ => None },
+        execute: |cpu, v| cpu.call(v),
+    },
+    InstructionDef {
+        mnemonic: "ret",
+        operands: NONE,
+        description: "returns to the address the last call pushed; clears carry",
+        encoding: Encoding::Prefix(EXT, 0b1010),
+        token: |_| Token::Ret,
+        value: |t| match t { Token::Ret => Some(0), _ => None },
+        execute: |cpu, _| cpu.ret(),
+    },
+    InstructionDef {
+        mnemonic: "hlt",
+        operands: NONE,
+        description: "stops the cpu until an interrupt",
+        encoding: Encoding::Prefix(EXT, 0b1011),
+        token: |_| Token::Hlt,
+        value: |t| match t { Token::Hlt => Some(0), _ => None },
+        execute: |cpu, _| { cpu.halted = Some(HaltReason::Hlt); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "nop",
+        operands: NONE,
+        description: "does nothing",
+        encoding: Encoding::Prefix(EXT, 0b1100),
+        token: |_| Token::Nop,
+        value: |t| match t { Token::Nop => Some(0), _ => None },
+        execute: |_, _| Ok(()),
+    },
+    InstructionDef {
+        mnemonic: "reti",
+        operands: NONE,
+        description: "returns from an interrupt, restoring carry and enabling interrupts",
+        encoding: Encoding::Prefix(EXT, 0b1101),
+        token: |_| Token::Reti,
+        value: |t| match t { Token::Reti => Some(0), _ => None },
+        execute: |cpu, _| cpu.reti(),
+    },
+    InstructionDef {
+        mnemonic: "ei",
+        operands: NONE,
+        description: "enables interrupts; clears carry",
+        encoding: Encoding::Prefix(EXT, 0b1110),
+        token: |_| Token::Ei,
+        value: |t| match t { Token::Ei => Some(0), _ => None },
+        execute: |cpu, _| { cpu.set_interrupt_enable(1); Ok(()) },
+    },
+    InstructionDef {
+        mnemonic: "di",
+        operands: NONE,
+        description: "disables interrupts; clears carry",
+        encoding: Encoding::Prefix(EXT, 0b1111),
+        token: |_| Token::Di,
+        value: |t| match t { Token::Di => Some(0), _ => None },
+        execute: |cpu, _| { cpu.set_interrupt_enable(0); Ok(()) },
+    },
+];
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Td4;
+
+impl Td4 {
+    pub fn new() -> Self {
+        Td4
+    }
+}
+
+impl Isa for Td4 {
+    fn name(&self) -> &'static str {
+        "td4"
+    }
+
+    fn registers(&self) -> &'static [RegisterLayout] {
+        &TD4_REGISTERS
+    }
+
+    fn instructions(&self) -> &'static [InstructionDef<Self>] {
+        &TD4_INSTRUCTIONS
+    }
+}
+
+
+#[cfg(test)]
+mod td4_tests {
+    use crate::emulator::instructions::{Address, Token};
+    use crate::emulator::isa::{Isa, Operand};
+    use crate::emulator::parser::Parser;
+    use crate::emulator::register::{Register, RegisterOp};
+    use crate::emulator::td4::Td4;
+
+    #[test]
+    fn decode_test() {
+        let td4 = Td4::new();
+        let words = [0b00110101, 0b10000001, 0b00010000, 0b10000001];
+
+        let (token, size) = td4.decode(&words, 0).unwrap();
+        assert!(matches!(token, Token::Mov(RegisterOp::A, 0b0101)));
+        assert_eq!(size, 1);
+
+        let (token, size) = td4.decode(&words, 1).unwrap();
+        assert!(matches!(token, Token::St(Address::Direct(0b00010000))));
+        assert_eq!(size, 2);
+
+        assert_eq!(td4.decode(&words, 3).unwrap_err().msg(), "Missing operand word");
+        assert_eq!(td4.decode(&[0b10001000, 0b00001111], 0).unwrap_err().msg(), "No match instruction");
+    }
+
+    // every table entry goes text -> token -> rom words -> token -> text unchanged
+    #[test]
+    fn table_round_trip_test() {
+        let td4 = Td4::new();
+
+        for def in td4.instructions() {
+            let value = match def.value_operand() {
+                Some(Operand::Address) | Some(Operand::Memory) => 0b10100101,
+                Some(_) => 0b0101,
+                None => 0,
+            };
+            let text = td4.disassemble(&(def.token)(value)).unwrap();
+
+            let mut parser = Parser::new(vec![text.clone()]);
+            let tokens = parser.parse().unwrap();
+            assert_eq!(tokens.len(), 1, "{}", text);
+            assert_eq!((def.value)(&tokens[0]), Some(value), "{}", text);
+
+            let words = td4.encode(&tokens[0]).unwrap();
+            assert_eq!(words.len(), tokens[0].size(), "{}", text);
+            let (token, size) = td4.decode(&words, 0).unwrap();
+            assert_eq!(size, words.len(), "{}", text);
+            assert_eq!(td4.disassemble(&token).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn table_encodings_unique_test() {
+        let td4 = Td4::new();
+        let instructions = td4.instructions();
+
+        for (i, def) in instructions.iter().enumerate() {
+            for other in &instructions[i + 1..] {
+                assert!(def.encoding != other.encoding, "{} and {} share an encoding", def.mnemonic, other.mnemonic);
+                assert!(
+                    def.mnemonic != other.mnemonic || def.operands != other.operands,
+                    "{} is defined twice", def.mnemonic
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn register_layout_test() {
+        let mut register = Register::new();
+        register.set_register_a(0b0101);
+        register.set_carry_flag(1);
+
+        let values: Vec<(&str, u16)> = Td4::new().registers().iter()
+            .map(|r| (r.name, (r.read)(&register)))
+            .collect();
+        assert_eq!(values, vec![("A", 0b0101), ("B", 0), ("C", 1), ("Z", 0), ("IE", 0)]);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::emulator::memory_map::Device;
+use crate::EmulatorErr;
+
+// register offsets inside the mapped timer
+pub const TIMER_RELOAD: usize = 0;
+pub const TIMER_PRESCALER: usize = 1;
+pub const TIMER_CONTROL: usize = 2;
+pub const TIMER_REGISTERS: usize = 3;
+
+// input line the overflow flag drives by default, read by in A / in B
+pub const TIMER_INPUT_BIT: u8 = 0b1000;
+
+// 4 bit up counter ticked once per executed instruction
+//   reload:    write restarts the count from the value, read gives the count
+//   prescaler: the count steps once every prescaler + 1 instructions
+//   control:   write bit 0 runs (1) or stops (0) the timer and clears overflow,
+//              read gives run in bit 0 and overflow in bit 1
+#[derive(Debug)]
+pub struct Timer {
+    counter: u8,
+    reload: u8,
+    prescaler: u8,
+    prescale_count: u8,
+    running: bool,
+    overflow: bool,
+    interrupt: bool,
+    input_mask: u8,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            counter: 0,
+            reload: 0,
+            prescaler: 0,
+            prescale_count: 0,
+            running: false,
+            overflow: false,
+            interrupt: false,
+            input_mask: TIMER_INPUT_BIT,
+        }
+    }
+
+    // input lines the overflow flag drives, 0 keeps it off the input
+    pub fn set_input_mask(&mut self, mask: u8) {
+        self.input_mask = mask & 0x0f;
+    }
+
+    pub fn counter(&self) -> u8 {
+        self.counter
+    }
+
+    pub fn overflow(&self) -> bool {
+        self.overflow
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    fn read(&mut self, offset: usize) -> Result<u8, EmulatorErr> {
+        match offset {
+            TIMER_RELOAD => Ok(self.counter),
+            TIMER_PRESCALER => Ok(self.prescaler),
+            TIMER_CONTROL => Ok(self.running as u8 | (self.overflow as u8) << 1),
+            _ => Err(EmulatorErr::new(&format!("Timer has no register {}", offset))),
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: u8) -> Result<(), EmulatorErr> {
+        match offset {
+            TIMER_RELOAD => {
+                self.reload = value & 0x0f;
+                self.counter = self.reload;
+                self.prescale_count = 0;
+            },
+            TIMER_PRESCALER => self.prescaler = value & 0x0f,
+            TIMER_CONTROL => {
+                self.running = value & 0b0001 == 1;
+                self.overflow = false;
+            },
+            _ => return Err(EmulatorErr::new(&format!("Timer has no register {}", offset))),
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self) {
+        if !self.running {
+            return;
+        }
+
+        if self.prescale_count < self.prescaler {
+            self.prescale_count += 1;
+            return;
+        }
+        self.prescale_count = 0;
+
+        if self.counter == 0x0f {
+            self.counter = self.reload;
+            self.overflow = true;
+            self.interrupt = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    fn input(&self) -> u8 {
+        if self.overflow { self.input_mask } else { 0 }
+    }
+
+    // one request per overflow
+    fn interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt)
+    }
+}
+
+
+#[cfg(test)]
+mod timer_tests {
+    use crate::emulator::memory_map::Device;
+    use crate::emulator::timer::{Timer, TIMER_CONTROL, TIMER_INPUT_BIT, TIMER_PRESCALER, TIMER_RELOAD};
+
+    #[test]
+    fn timer_overflow_test() {
+        let mut timer = Timer::new();
+        timer.write(TIMER_RELOAD, 0b1101).unwrap();
+        timer.tick();
+        assert_eq!(timer.counter(), 0b1101);
+
+        timer.write(TIMER_CONTROL, 1).unwrap();
+        timer.tick();
+        timer.tick();
+        assert_eq!(timer.counter(), 0b1111);
+        assert!(!timer.overflow());
+
+        timer.tick();
+        assert_eq!(timer.counter(), 0b1101);
+        assert!(timer.overflow());
+        assert_eq!(timer.input(), TIMER_INPUT_BIT);
+        assert_eq!(timer.read(TIMER_CONTROL).unwrap(), 0b11);
+        assert!(timer.interrupt());
+        assert!(!timer.interrupt());
+
+        timer.write(TIMER_CONTROL, 1).unwrap();
+        assert!(!timer.overflow());
+        assert_eq!(timer.input(), 0);
+    }
+
+    #[test]
+    fn timer_prescaler_test() {
+        let mut timer = Timer::new();
+        timer.write(TIMER_PRESCALER, 2).unwrap();
+        timer.write(TIMER_CONTROL, 1).unwrap();
+        for _ in 0..5 {
+            timer.tick();
+        }
+        assert_eq!(timer.counter(), 1);
+        timer.tick();
+        assert_eq!(timer.counter(), 2);
+
+        assert!(timer.write(3, 0).is_err());
+        assert!(timer.read(3).is_err());
+    }
+
+    #[test]
+    fn timer_input_mask_test() {
+        let mut timer = Timer::new();
+        timer.write(TIMER_RELOAD, 0b1111).unwrap();
+        timer.write(TIMER_CONTROL, 1).unwrap();
+        timer.tick();
+        assert!(timer.overflow());
+
+        timer.set_input_mask(0b0010);
+        assert_eq!(timer.input(), 0b0010);
+        timer.set_input_mask(0);
+        assert_eq!(timer.input(), 0);
+        assert_eq!(timer.read(TIMER_CONTROL).unwrap(), 0b11);
+    }
+}
@@ -1,11 +1,44 @@
+use std::fmt;
+
+use emulator::source_map::SourceLocation;
+
+pub mod emulator;
 
 #[derive(Debug)]
 pub struct EmulatorErr {
     msg: String,
+    location: Option<SourceLocation>,
+    text_start: usize,
 }
 
 impl EmulatorErr {
     pub fn new(msg: &str) -> Self {
-        Self { msg: msg.to_string() }
+        Self { msg: msg.to_string(), location: None, text_start: 0 }
+    }
+
+    // error about the source at location, shown as "location: msg"
+    pub fn at(location: &SourceLocation, msg: &str) -> Self {
+        let prefix = format!("{}: ", location);
+        Self { msg: format!("{}{}", prefix, msg), location: Some(location.clone()), text_start: prefix.len() }
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    // source position the error is about, if it came from source
+    pub fn location(&self) -> Option<&SourceLocation> {
+        self.location.as_ref()
+    }
+
+    // the message without its location
+    pub fn text(&self) -> &str {
+        &self.msg[self.text_start..]
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for EmulatorErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
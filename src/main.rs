@@ -1,29 +1,190 @@
-use std::{env, fs::File, io::{BufReader, BufRead}};
-use emulator::executor::CPUemulator;
-
-mod emulator;
-mod lib;
+use std::{env, fs, process};
+use cpu_emulator::emulator::{self, executor::CPUemulator, machine::MachineConfig, parser::{Dialect, SymbolKind}, timer::{Timer, TIMER_REGISTERS}};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     if args.len() <= 1 {
-        panic!("necessary command arg: [file_path]")
+        panic!("necessary command arg: [lint|fmt] [file_path] [--check] [--source-map map_path] [--rom-size words] [--address-width bits] [--ram-size words] [--rom-banks count] [--bank-port-base binary] [--port-address binary] [--timer-address binary] [--timer-input binary] [--stack-depth count] [--dialect native|book] [-I include_dir] [-D name[=value]] [--fill binary] [--disassemble] [--symbols] [--extended-alu] [--stack-wrap]")
+    }
+
+    // lint file_path ... reports suspicious code instead of running it,
+    // fmt file_path ... rewrites it in the canonical layout
+    let lint = args[1] == "lint";
+    let fmt = args[1] == "fmt";
+    let file_path = args.get(if lint || fmt { 2 } else { 1 }).unwrap_or_else(|| panic!("{} needs a file_path", args[1]));
+    let mut source_map_path = None;
+    let mut rom_size = MachineConfig::td4().rom_size();
+    let mut address_width = MachineConfig::td4().address_width();
+    let mut ram_size = 0;
+    let mut rom_banks = 1;
+    let mut bank_port_base = None;
+    let mut port_address = None;
+    let mut timer_address = None;
+    let mut timer_input = None;
+    let mut disassemble = false;
+    let mut symbols = false;
+    let mut extended_alu = false;
+    let mut stack_depth = MachineConfig::td4().stack_depth();
+    let mut stack_wrap = false;
+    let mut dialect = Dialect::Native;
+    let mut include_paths = Vec::new();
+    let mut defines = Vec::new();
+    let mut fill = 0;
+
+    let mut check = false;
+
+    let mut options = args.iter().skip(if lint || fmt { 3 } else { 2 });
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--disassemble" => { disassemble = true; continue },
+            "--symbols" => { symbols = true; continue },
+            "--extended-alu" => { extended_alu = true; continue },
+            "--stack-wrap" => { stack_wrap = true; continue },
+            "--check" if fmt => { check = true; continue },
+            _ => {},
+        }
+
+        let value = options.next().unwrap_or_else(|| panic!("{} needs a value", option));
+        match option.as_str() {
+            "--source-map" => source_map_path = Some(value),
+            "--rom-size" => rom_size = value.parse().expect("--rom-size must be a number"),
+            "--address-width" => address_width = value.parse().expect("--address-width must be a number"),
+            "--ram-size" => ram_size = value.parse().expect("--ram-size must be a number"),
+            "--rom-banks" => rom_banks = value.parse().expect("--rom-banks must be a number"),
+            "--bank-port-base" => bank_port_base = Some(u8::from_str_radix(value, 2).expect("--bank-port-base must be binary")),
+            "--port-address" => port_address = Some(usize::from_str_radix(value, 2).expect("--port-address must be binary")),
+            "--timer-address" => timer_address = Some(usize::from_str_radix(value, 2).expect("--timer-address must be binary")),
+            "--timer-input" => timer_input = Some(u8::from_str_radix(value, 2).expect("--timer-input must be binary")),
+            "--stack-depth" => stack_depth = value.parse().expect("--stack-depth must be a number"),
+            "--dialect" => dialect = value.parse().unwrap(),
+            "-I" | "--include-path" => include_paths.push(value),
+            "--fill" => fill = u8::from_str_radix(value, 2).expect("--fill must be binary"),
+            "-D" | "--define" => {
+                let (name, value) = value.split_once('=').unwrap_or((value, "1"));
+                let value = emulator::expression::evaluate(value, &|_| None).unwrap();
+                defines.push((name, value));
+            },
+            _ => panic!("unknown argument: {}", option),
+        }
+    }
+
+    let source = fs::read_to_string(file_path).unwrap();
+    let operations: Vec<String> = source.lines().map(String::from).collect();
+
+    let mut instructions = emulator::parser::Parser::with_file_name(file_path, operations);
+    instructions.set_dialect(dialect);
+    for path in include_paths {
+        instructions.add_include_path(path);
+    }
+    for (name, value) in defines {
+        instructions.define(name, value);
+    }
+
+    if fmt {
+        // source that does not parse is left as it is
+        let formatted = emulator::formatter::Formatter::new().format(&mut instructions, &source).unwrap_or_else(|e| {
+            eprintln!("{}", e.msg());
+            process::exit(1);
+        });
+        if check {
+            // --check changes nothing and fails on the first line that differs
+            if source != formatted {
+                let line = source.lines().zip(formatted.lines())
+                    .position(|(a, b)| a != b)
+                    .unwrap_or(source.lines().count().min(formatted.lines().count()));
+                println!("{}:{}: not formatted", file_path, line + 1);
+                process::exit(1);
+            }
+        } else if source != formatted {
+            fs::write(file_path, formatted).unwrap();
+        }
+        return;
     }
 
-    let file = File::open(args.get(1).unwrap()).unwrap();
-    let reader = BufReader::new(&file);
-    let operations: Vec<String> = reader.lines().map(|line| line.unwrap()).collect();
+    let mut config = MachineConfig::new(rom_size, address_width).unwrap();
+    config.set_ram_size(ram_size);
+    config.set_rom_banks(rom_banks);
+    config.set_bank_port_base(bank_port_base);
+    config.set_extended_alu(extended_alu);
+    config.set_stack_depth(stack_depth);
+    config.set_stack_wrap(stack_wrap);
+    config.validate().unwrap();
 
-    let mut instructions = emulator::parser::Parser::new(operations);
     let token = instructions.parse().unwrap();
+    instructions.check_layout(&token, config.rom_size(), config.address_width()).unwrap();
+    for warning in instructions.warnings() {
+        eprintln!("warning: {}", warning);
+    }
+
+    if lint {
+        let lints = emulator::lint::Linter::new().lint(&instructions, &token);
+        for lint in &lints {
+            println!("{}", lint);
+        }
+        process::exit(if lints.is_empty() { 0 } else { 1 });
+    }
+
+    let starts = <emulator::parser::Parser>::instruction_addresses(&token);
+    let mut compiler = emulator::compiler::Compiler::new();
+    compiler.set_fill(fill);
+    compiler.set_rom_size(config.rom_size());
+    let (dec, source_map) = compiler.compile_with_source_map(token, instructions.locations()).unwrap();
+
+    if symbols {
+        for symbol in instructions.symbols() {
+            println!("{}", symbol);
+        }
+    }
 
-    let compiler =emulator::compiler::Compiler::new();
-    let dec = compiler.compile(token).unwrap();
+    if disassemble {
+        for symbol in instructions.symbols().iter().filter(|s| s.kind == SymbolKind::Constant) {
+            println!(".equ {} {}", symbol.name, symbol.value_text());
+        }
+        // instructions a macro call expands to are indented under the call;
+        // only the words the source placed instructions at are decoded
+        let listing = emulator::disassembler::Disassembler::new().listing_with_data(&dec, &starts).unwrap();
+        let mut current_call = None;
+        for (address, text) in listing {
+            let location = source_map.get(address);
+            let call = instructions.expansions().iter().find(|e| Some(&e.location) == location);
+            match call {
+                Some(call) => {
+                    if current_call != Some(&call.location) {
+                        println!("{:04x}: {}", address, call.text);
+                    }
+                    println!("{:04x}:   {}", address, text);
+                    current_call = Some(&call.location);
+                },
+                None => {
+                    println!("{:04x}: {}", address, text);
+                    current_call = None;
+                },
+            }
+        }
+    }
+
+    if let Some(path) = source_map_path {
+        source_map.write_sidecar(path).unwrap();
+    }
 
-    let rom = emulator::adapter::Rom::new(dec);
+    let rom = emulator::adapter::Rom::with_source_map(dec, source_map);
     let register = emulator::register::Register::new();
     let port = emulator::adapter::Port::new(0b0000, 0b0000);
-    let mut emulator = CPUemulator::new(register, port, rom);
-    emulator.execute().unwrap();
+    let mut emulator = CPUemulator::with_config(config, register, port, rom).unwrap();
+    if let Some(address) = port_address {
+        emulator.memory_map.map_port(address).unwrap();
+    }
+    if timer_input.is_some() && timer_address.is_none() {
+        panic!("--timer-input needs --timer-address");
+    }
+    if let Some(address) = timer_address {
+        let mut timer = Timer::new();
+        if let Some(mask) = timer_input {
+            timer.set_input_mask(mask);
+        }
+        emulator.memory_map.map_device(address, TIMER_REGISTERS, Box::new(timer)).unwrap();
+    }
+    let reason = emulator.execute().unwrap();
+    println!("halted: {}", reason);
 }